@@ -1,20 +1,25 @@
+mod location;
 mod operators;
 mod tokenizer;
 
+use anyhow::bail;
+pub use location::{LineIndex, Location};
 pub use operators::Operator;
 use pest::iterators::Pairs;
 pub use tokenizer::{Token, Tokenizer};
 
+/// Parser generated from grammar.pest, an alternative to Ast::parse
 #[derive(pest_derive::Parser)]
 #[grammar = "grammar.pest"]
 pub struct BrainFuckParser;
 
+/// Operators along with the byte offset in the source each one was parsed from
 #[derive(Debug)]
-pub struct Ast(Vec<Operator>);
+pub struct Ast(Vec<Operator>, Vec<usize>);
 
 impl From<Tokenizer> for Ast {
     fn from(t: Tokenizer) -> Ast {
-        Ast::parse_from_tokenizer(&t.inner())
+        Ast::parse_from_tokenizer(&t.inner(), 0)
     }
 }
 
@@ -25,13 +30,41 @@ impl From<Pairs<'_, Rule>> for Ast {
 }
 
 impl Ast {
+    /// Parses source with the internal tokenizer, failing with the line and column of the
+    /// first unbalanced bracket instead of panicking on it
+    pub fn parse(source: &[u8]) -> anyhow::Result<Ast> {
+        let mut open = vec![];
+        for (offset, c) in source.iter().enumerate() {
+            match c {
+                b'[' => open.push(offset),
+                b']' if open.pop().is_none() => {
+                    bail!("unmatched ] at {}", Location::new(source, offset));
+                }
+                _ => {}
+            }
+        }
+        if let Some(offset) = open.pop() {
+            bail!("unmatched [ at {}", Location::new(source, offset));
+        }
+
+        Ok(Ast::from(Tokenizer::from(source)))
+    }
+
+    /// Operators without their offsets
     pub fn inner(&self) -> &[Operator] {
         &self.0
     }
 
+    /// Operators paired with their byte offset in the source
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Operator)> {
+        self.1.iter().copied().zip(self.0.iter())
+    }
+
     fn parse_from_pest(pairs: Pairs<Rule>) -> Ast {
         let mut ops = vec![];
+        let mut offsets = vec![];
         for pair in pairs {
+            let offset = pair.as_span().start();
             match pair.as_rule() {
                 Rule::Command => ops.push(Operator::from(
                     pair.as_span().as_str().bytes().next().unwrap(),
@@ -39,17 +72,19 @@ impl Ast {
                 Rule::Loop => ops.push(Operator::Loop(Ast::parse_from_pest(pair.into_inner()))),
                 Rule::Program => panic!("error we should never see a program rule"),
                 Rule::EOI => break,
-                _ => {}
+                _ => continue,
             }
+            offsets.push(offset);
         }
 
-        Ast(ops)
+        Ast(ops, offsets)
     }
 
-    fn parse_from_tokenizer(tokens: &[Token]) -> Ast {
+    fn parse_from_tokenizer(tokens: &[Token], base: usize) -> Ast {
         let mut sp = 0;
         let mut stack = 0;
         let mut ops = Vec::new();
+        let mut offsets = Vec::new();
 
         for (pc, token) in tokens.iter().enumerate() {
             if stack == 0 {
@@ -70,7 +105,8 @@ impl Ast {
                 };
 
                 if let Some(ins) = ins {
-                    ops.push(ins)
+                    ops.push(ins);
+                    offsets.push(base + pc);
                 };
             } else {
                 match token {
@@ -82,7 +118,9 @@ impl Ast {
                         if stack == 0 {
                             ops.push(Operator::Loop(Self::parse_from_tokenizer(
                                 &tokens[sp + 1..pc],
-                            )))
+                                base + sp + 1,
+                            )));
+                            offsets.push(base + sp);
                         }
                     }
                     _ => {}
@@ -90,6 +128,6 @@ impl Ast {
             }
         }
 
-        Ast(ops)
+        Ast(ops, offsets)
     }
 }
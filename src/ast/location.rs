@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// Human readable position of a byte offset in the source, line and column start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn new(source: &[u8], offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.iter().filter(|c| **c == b'\n').count() + 1;
+        let column = match before.iter().rposition(|c| *c == b'\n') {
+            Some(nl) => offset - nl,
+            None => offset + 1,
+        };
+
+        Self {
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+/// Offsets the lines of a source start at, for locating many offsets without rescanning it
+pub struct LineIndex(Vec<usize>);
+
+impl LineIndex {
+    pub fn new(source: &[u8]) -> Self {
+        let mut starts = vec![0];
+        starts.extend(
+            source
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+        Self(starts)
+    }
+
+    /// Same as Location::new on the indexed source
+    pub fn locate(&self, offset: usize) -> Location {
+        let line = self.0.partition_point(|start| *start <= offset);
+        Location {
+            offset,
+            line,
+            column: offset - self.0[line - 1] + 1,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_index_matches_location() {
+        let source = b"+[\n>.\n\n  <]\n";
+        let index = LineIndex::new(source);
+        for offset in 0..=source.len() {
+            assert_eq!(index.locate(offset), Location::new(source, offset));
+        }
+        assert_eq!(index.locate(9).to_string(), "4:3");
+    }
+}
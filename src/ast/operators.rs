@@ -1,5 +1,6 @@
 use crate::ast::Ast;
 
+/// A Brainfuck command, loops hold the commands between `[` and `]`
 #[derive(Debug)]
 pub enum Operator {
     IncPtr,
@@ -24,3 +25,17 @@ impl From<u8> for Operator {
         }
     }
 }
+
+impl From<&Operator> for u8 {
+    fn from(op: &Operator) -> Self {
+        match op {
+            Operator::IncPtr => b'>',
+            Operator::DecPtr => b'<',
+            Operator::Inc => b'+',
+            Operator::Dec => b'-',
+            Operator::Out => b'.',
+            Operator::In => b',',
+            Operator::Loop(_) => b'[',
+        }
+    }
+}
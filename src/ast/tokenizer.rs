@@ -2,6 +2,7 @@ use std::io::{self, Read, Write};
 use std::str::FromStr;
 use std::{char, fmt};
 
+/// A single source byte, bytes other than the eight commands are Nop
 #[derive(Debug)]
 pub enum Token {
     IncPtr,
@@ -70,6 +71,7 @@ impl fmt::Display for Token {
     }
 }
 
+/// Tokens of a whole source file in order
 #[derive(Default)]
 pub struct Tokenizer(Vec<Token>);
 
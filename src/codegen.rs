@@ -1,19 +1,133 @@
 use crate::ast::{Ast, Operator};
-use inkwell::execution_engine::JitFunction;
-use inkwell::module::Module;
-use inkwell::values::{FunctionValue, PointerValue};
+use crate::dump::TapeSnapshot;
+use crate::runtime;
+use crate::tape::TapeConfig;
+use crate::transpile::Eof;
+use anyhow::{anyhow, bail, Context as _};
+use inkwell::basic_block::BasicBlock;
+use inkwell::debug_info::{
+    debug_metadata_version, AsDIScope, DIFlags, DIFlagsConstants, DIScope, DWARFEmissionKind,
+    DWARFSourceLanguage, DebugInfoBuilder,
+};
+use inkwell::execution_engine::{ExecutionEngine, JitFunction};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{
+    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetData, TargetMachine,
+    TargetTriple,
+};
+use inkwell::types::StructType;
+use inkwell::values::{FunctionValue, GlobalValue, IntValue, PointerValue};
 use inkwell::OptimizationLevel;
 use inkwell::{builder::Builder, context::Context, module::Linkage};
 use inkwell::{AddressSpace, IntPredicate};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+mod jit;
+mod library;
+
+pub use jit::JitProgram;
+use library::LibraryParams;
+pub use library::{library_header, validate_identifier};
+
+/// Artifacts IRCodegen::emit writes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// Textual IR after optimization
+    LlvmIr,
+    /// Bitcode of the same module
+    LlvmBc,
+    /// Assembly for the target machine
+    Asm,
+    /// Relocatable object file
+    Obj,
+    /// Object linked with the runtime by the system linker
+    Exe,
+    /// Static library holding the object, made with ar
+    Lib,
+    /// C declarations of a library mode function
+    Header,
+}
+
+impl Emit {
+    /// Name used for the kind on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Emit::LlvmIr => "llvm-ir",
+            Emit::LlvmBc => "llvm-bc",
+            Emit::Asm => "asm",
+            Emit::Obj => "obj",
+            Emit::Exe => "exe",
+            Emit::Lib => "lib",
+            Emit::Header => "header",
+        }
+    }
+
+    /// Extension of the file written next to the program when no path is given
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Emit::LlvmIr => Some("ll"),
+            Emit::LlvmBc => Some("bc"),
+            Emit::Asm => Some("s"),
+            Emit::Obj => Some("o"),
+            Emit::Exe => None,
+            Emit::Lib => Some("a"),
+            Emit::Header => Some("h"),
+        }
+    }
+}
+
+// Bytes of output the generated code collects before writing them out
+const OUTPUT_BUFFER: u64 = 8192;
+
+// DWARF state while a module with debug info is being built
+struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    scope: DIScope<'ctx>,
+    // Line and column of every source byte
+    positions: Vec<(u32, u32)>,
+}
+
+/// Generates LLVM IR for a program and runs it with the JIT or compiles it ahead of time.
+/// Configured with the builder methods, every one of them takes and returns self
 pub struct IRCodegen<'a, 'ctx> {
     ast: &'a Ast,
     funcs: HashMap<String, FunctionValue<'ctx>>,
+    globals: HashMap<String, GlobalValue<'ctx>>,
+    // Memory cap in cells when the tape is grown at runtime by bf_tape_grow
+    growable: Option<u64>,
+    tape: TapeConfig,
+    // Calls bf_tape_dump with the final tape before main returns
+    dump: bool,
+    // Wraps the pointer around the ends of memory
+    circular: bool,
+    // Calls bf_bounds_error when the pointer leaves memory
+    checked: bool,
+    // Moves the tape between PROT_NONE guard pages with bf_guard_tape
+    guarded: bool,
+    // Calls putchar for every . instead of collecting output in out_buf
+    unbuffered: bool,
+    // What , stores once getchar or the library's read callback runs out of input
+    eof: Eof,
+    // Source file and contents line tables and the tape pointer variable are generated for
+    debug_source: Option<(PathBuf, &'a [u8])>,
+    debug_info: Option<DebugInfo<'a>>,
+    // Builds a reentrant bf_<name> function taking the tape and I/O callbacks instead of main
+    library: Option<String>,
+    library_params: Option<LibraryParams<'a>>,
+    // Frees the tape and returns 1 from main, where failed runtime calls branch to
+    error_block: Option<BasicBlock<'a>>,
+    // Pass pipeline run over the module before it is handed to the JIT or clang
+    opt_level: OptimizationLevel,
+    // Machine code generation level of the JIT execution engine
+    jit_opt_level: OptimizationLevel,
+    // Triple, cpu and features the module is generated for, the host when unset
+    target: Option<String>,
+    cpu: Option<String>,
+    features: Option<String>,
     context: Context,
 }
 
@@ -22,47 +136,353 @@ impl<'a, 'ctx> From<&'a Ast> for IRCodegen<'a, 'ctx> {
         IRCodegen {
             ast: source,
             funcs: HashMap::new(),
+            globals: HashMap::new(),
+            growable: None,
+            tape: TapeConfig::default(),
+            dump: false,
+            circular: false,
+            checked: false,
+            guarded: false,
+            unbuffered: false,
+            eof: Eof::default(),
+            debug_source: None,
+            debug_info: None,
+            library: None,
+            library_params: None,
+            error_block: None,
+            opt_level: OptimizationLevel::None,
+            jit_opt_level: OptimizationLevel::None,
+            target: None,
+            cpu: None,
+            features: None,
             context: Context::create(),
         }
     }
 }
 
 impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
-    pub fn jit(&self) {
-        let module = self.build_module();
-        let exec_engine = module
-            .create_jit_execution_engine(OptimizationLevel::None)
-            .unwrap();
+    /// Length, start cell and initial contents of the tape
+    pub fn tape_config(mut self, tape: TapeConfig) -> Self {
+        self.tape = tape;
+        self
+    }
+
+    /// Moves the tape to the heap and grows it in both directions up to cap cells once the pointer leaves it
+    pub fn growable_tape(mut self, cap: u64) -> Self {
+        self.growable = Some(cap);
+        self
+    }
+
+    /// Moving past either end of the tape wraps around to the other one
+    pub fn circular_tape(mut self) -> Self {
+        self.circular = true;
+        self
+    }
+
+    /// Validates the pointer before every cell access and reports the source offset of the
+    /// instruction that would have touched memory outside of the tape. Like the interpreter,
+    /// moves may take the pointer off the tape as long as no cell there is accessed
+    pub fn checked(mut self) -> Self {
+        self.checked = true;
+        self
+    }
+
+    /// Runs the program on a copy of the tape mapped between PROT_NONE guard pages, stepping off of it
+    /// faults and the runtime's SIGSEGV handler reports the cell and ends the process instead of
+    /// crashing. Costs nothing per move unlike checked but overflows are only caught past the page
+    /// holding the last cell. The cells are copied back into memory once the program ends.
+    /// Output that is still buffered when the process ends this way is lost, combine it with
+    /// unbuffered to see everything written up to the fault
+    #[cfg(unix)]
+    pub fn guarded(mut self) -> Self {
+        self.guarded = true;
+        self
+    }
+
+    /// Writes every byte as soon as the program outputs it, for interactive programs that
+    /// prompt without reading input. Output is otherwise buffered and written out once the
+    /// buffer is full, before reading input and when the program ends
+    pub fn unbuffered(mut self) -> Self {
+        self.unbuffered = true;
+        self
+    }
+
+    /// What , stores once input runs out, -1 by default
+    pub fn eof(mut self, eof: Eof) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Emits DWARF line tables mapping every instruction to its line and column in source,
+    /// read from path, and describes the tape pointer as a variable named ptr
+    pub fn debug_info(mut self, path: &Path, source: &'a [u8]) -> Self {
+        self.debug_source = Some((path.to_path_buf(), source));
+        self
+    }
+
+    /// Compiles the program to int bf_<name>(uint8_t *tape, size_t len, bf_read_fn read,
+    /// bf_write_fn write, void *ctx) as declared by library_header. Every move is bounds checked
+    /// against the caller's tape, the tape options and debug info do not apply
+    pub fn library(mut self, name: &str) -> Self {
+        self.library = Some(name.to_string());
+        self
+    }
+
+    /// The JIT hands the tape back from jit, compiled programs write it out
+    /// according to the BF_TAPE_DUMP and BF_TAPE_DUMP_FORMAT environment variables
+    pub fn dump_tape(mut self) -> Self {
+        self.dump = true;
+        self
+    }
+
+    /// Runs mem2reg, instcombine, gvn and the loop passes followed by LLVM's default
+    /// pipeline for level, None leaves the generated IR untouched
+    pub fn optimize(mut self, level: OptimizationLevel) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Level the JIT lowers the optimized module to machine code with
+    pub fn jit_opt_level(mut self, level: OptimizationLevel) -> Self {
+        self.jit_opt_level = level;
+        self
+    }
+
+    /// Cross compiles for triple instead of the host, x86_64, aarch64, riscv64 and wasm32
+    /// are registered. Only affects emit and compile, the JIT always runs on the host
+    pub fn target(mut self, triple: &str) -> Self {
+        self.target = Some(triple.to_string());
+        self
+    }
+
+    /// Cpu for ahead of time compilation, defaults to the host cpu when compiling for the host
+    /// and to generic otherwise. The JIT always targets the host cpu
+    pub fn cpu(mut self, cpu: &str) -> Self {
+        self.cpu = Some(cpu.to_string());
+        self
+    }
+
+    /// Comma separated list such as +neon,-sve, applies and defaults like cpu
+    pub fn features(mut self, features: &str) -> Self {
+        self.features = Some(features.to_string());
+        self
+    }
+
+    /// Returns the final tape when dump_tape is enabled, and the error the runtime reported
+    /// when the program failed
+    pub fn jit(&self) -> anyhow::Result<Option<TapeSnapshot>> {
+        self.run_jit(false)
+    }
+
+    /// Runs the program with , reading from input and . writing to output instead of the
+    /// process's stdin and stdout, so the output can be captured and compared
+    pub fn jit_with_io(
+        &self,
+        input: &mut dyn io::Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<TapeSnapshot>> {
+        runtime::with_host_io(input, output, || self.run_jit(true))
+    }
+
+    fn run_jit(&self, host_io: bool) -> anyhow::Result<Option<TapeSnapshot>> {
+        let machine = self.host_machine()?;
+        let module = self.build_module(&machine.get_target_data());
+        let exec_engine = self.build_engine(&module, &machine, host_io)?;
 
         unsafe {
             type Func = unsafe extern "C" fn() -> i32;
-            let f: JitFunction<Func> = exec_engine.get_function("main").unwrap();
-            f.call();
+            let f: JitFunction<Func> = exec_engine
+                .get_function("main")
+                .map_err(|e| anyhow!("unable to look up main: {}", e))?;
+            runtime::finish(f.call())
         }
     }
 
-    pub fn compile(&self, name: &str) {
-        std::fs::create_dir_all("./out").expect("unable to create output dir");
-        let path = Path::new("./out");
-        let ir = self.build_module().to_string();
-        let ir_filepath = path.join("output.ll");
+    // Generates code for the host, which the JIT runs on
+    fn host_machine(&self) -> anyhow::Result<TargetMachine> {
+        self.target_machine(&TargetMachine::get_default_triple(), true)
+    }
+
+    // Optimizes module for the host and binds its external calls to the Rust runtime
+    fn build_engine<'m>(
+        &self,
+        module: &Module<'m>,
+        machine: &TargetMachine,
+        host_io: bool,
+    ) -> anyhow::Result<ExecutionEngine<'m>> {
+        self.optimize_module(module, machine)?;
+        let exec_engine = module
+            .create_jit_execution_engine(self.jit_opt_level)
+            .map_err(|e| anyhow!("unable to create the JIT: {}", e))?;
+
+        if let Some(grow_fn) = module.get_function("bf_tape_grow") {
+            exec_engine.add_global_mapping(&grow_fn, runtime::bf_tape_grow as *const () as usize);
+        }
+        if let Some(free_fn) = module.get_function("bf_tape_free") {
+            exec_engine.add_global_mapping(&free_fn, runtime::bf_tape_free as *const () as usize);
+        }
+        if let Some(dump_fn) = module.get_function("bf_tape_dump") {
+            exec_engine.add_global_mapping(&dump_fn, runtime::bf_tape_dump as *const () as usize);
+        }
+        if let Some(error_fn) = module.get_function("bf_bounds_error") {
+            exec_engine
+                .add_global_mapping(&error_fn, runtime::bf_bounds_error as *const () as usize);
+        }
+        #[cfg(unix)]
+        if let Some(guard_fn) = module.get_function("bf_guard_tape") {
+            exec_engine.add_global_mapping(&guard_fn, runtime::bf_guard_tape as *const () as usize);
+        }
+        #[cfg(unix)]
+        if let Some(unguard_fn) = module.get_function("bf_unguard_tape") {
+            exec_engine
+                .add_global_mapping(&unguard_fn, runtime::bf_unguard_tape as *const () as usize);
+        }
+        if host_io {
+            // Declarations the program never calls may have been optimized away
+            if let Some(getchar_fn) = module.get_function("getchar") {
+                exec_engine.add_global_mapping(
+                    &getchar_fn,
+                    runtime::bf_host_getchar as *const () as usize,
+                );
+            }
+            if let Some(putchar_fn) = module.get_function("putchar") {
+                exec_engine.add_global_mapping(
+                    &putchar_fn,
+                    runtime::bf_host_putchar as *const () as usize,
+                );
+            }
+            if let Some(write_fn) = module.get_function("write") {
+                exec_engine
+                    .add_global_mapping(&write_fn, runtime::bf_host_write as *const () as usize);
+            }
+        }
+
+        Ok(exec_engine)
+    }
+
+    /// Writes an object file when output ends in .o and a static library when it ends in .a,
+    /// otherwise links the object together with the runtime into an executable using $CC or cc
+    pub fn compile(&self, output: &Path) -> anyhow::Result<()> {
+        let kind = match output.extension().and_then(|ext| ext.to_str()) {
+            Some("o") => Emit::Obj,
+            Some("a") => Emit::Lib,
+            _ => Emit::Exe,
+        };
+        self.emit(&[(kind, Some(output.to_path_buf()))])
+    }
+
+    /// Builds and optimizes the module once and writes every artifact from it,
+    /// artifacts without a path go to stdout
+    pub fn emit(&self, artifacts: &[(Emit, Option<PathBuf>)]) -> anyhow::Result<()> {
+        let triple = match &self.target {
+            Some(triple) => TargetTriple::create(triple),
+            None => TargetMachine::get_default_triple(),
+        };
+        // Created first so the module is built against the target's pointer size
+        let machine = self.target_machine(&triple, false)?;
+        let target_data = machine.get_target_data();
+        let module = match &self.library {
+            Some(name) => self.build_library_module(name, &target_data),
+            None => self.build_module(&target_data),
+        };
+        self.optimize_module(&module, &machine)?;
+
+        for (kind, path) in artifacts {
+            let path = path.as_deref();
+            match kind {
+                Emit::LlvmIr => write_artifact(path, module.print_to_string().to_bytes())?,
+                Emit::LlvmBc => write_artifact(path, module.write_bitcode_to_memory().as_slice())?,
+                Emit::Asm | Emit::Obj => {
+                    let file_type = match kind {
+                        Emit::Asm => FileType::Assembly,
+                        _ => FileType::Object,
+                    };
+                    let buffer = machine
+                        .write_to_memory_buffer(&module, file_type)
+                        .map_err(|e| anyhow!("unable to generate {}: {}", kind.name(), e))?;
+                    write_artifact(path, buffer.as_slice())?;
+                }
+                Emit::Header => match &self.library {
+                    Some(name) => write_artifact(path, library_header(name).as_bytes())?,
+                    None => bail!("headers are only generated for library mode"),
+                },
+                Emit::Exe if self.library.is_some() => {
+                    bail!("library mode has no main to link an executable from, emit lib or obj")
+                }
+                Emit::Exe if triple.as_str().to_string_lossy().starts_with("wasm") => {
+                    bail!(
+                        "executables are not supported for {}, emit an object instead",
+                        triple
+                    )
+                }
+                Emit::Exe => {
+                    // Created with a unique name only this user can access and removed along
+                    // with everything in it once dropped, including when linking fails
+                    let temp = temp_dir()?;
+                    let object = temp.path().join("bf.o");
+                    let exe = path.map_or_else(|| temp.path().join("bf"), Path::to_path_buf);
 
-        let mut file = File::create(&ir_filepath).expect("unable to create IR file");
-        file.write_all(ir.as_bytes())
-            .expect("unable to write IR to file");
+                    machine
+                        .write_to_file(&module, FileType::Object, &object)
+                        .map_err(|e| anyhow!("unable to write {}: {}", object.display(), e))?;
+                    self.link(&object, &exe)?;
 
-        let output = path.join(name);
-        let status = Command::new("clang")
-            .arg("-o")
-            .arg(output)
-            .arg(ir_filepath)
-            .status()
-            .expect("failed to execute clang");
+                    if path.is_none() {
+                        let bytes =
+                            std::fs::read(&exe).context("unable to read linked executable")?;
+                        write_artifact(None, &bytes)?;
+                    }
+                }
+                Emit::Lib => {
+                    let temp = temp_dir()?;
+                    let object = temp.path().join("bf.o");
+                    let lib = path.map_or_else(|| temp.path().join("libbf.a"), Path::to_path_buf);
 
-        println!("{:?}", status);
+                    machine
+                        .write_to_file(&module, FileType::Object, &object)
+                        .map_err(|e| anyhow!("unable to write {}: {}", object.display(), e))?;
+                    archive(&object, &lib)?;
+
+                    if path.is_none() {
+                        let bytes = std::fs::read(&lib).context("unable to read static library")?;
+                        write_artifact(None, &bytes)?;
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
-    pub fn build_module(&self) -> Module {
+    // The runtime source is written next to object, which lives in a private temporary directory
+    fn link(&self, object: &Path, output: &Path) -> anyhow::Result<()> {
+        let linker = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let mut command = Command::new(&linker);
+        command.arg("-o").arg(output).arg(object);
+
+        if self.needs_runtime() {
+            let runtime_path = object.with_file_name("bf_runtime.c");
+            std::fs::write(&runtime_path, runtime::C_SOURCE)
+                .with_context(|| format!("unable to write {}", runtime_path.display()))?;
+            command
+                .arg(format!("-O{}", self.opt_level as u32))
+                .arg(&runtime_path);
+        }
+
+        let status = match command.status() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => bail!(
+                "linker {} not found, install a C toolchain, set CC or write an object file with -o <name>.o",
+                linker
+            ),
+            status => status.with_context(|| format!("unable to run linker {}", linker))?,
+        };
+        if !status.success() {
+            bail!("linker {} failed with {}", linker, status);
+        }
+        Ok(())
+    }
+
+    /// Sizes follow target_data, which comes from the machine the module is generated for
+    pub fn build_module(&self, target_data: &TargetData) -> Module<'_> {
         let module = self.context.create_module("brainfuck_rs");
         let builder = self.context.create_builder();
 
@@ -77,13 +497,8 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
         let getchar_fn_type = self.context.i32_type().fn_type(&[], false);
         let getchar_fn = module.add_function("getchar", getchar_fn_type, Some(Linkage::External));
 
-        // Setup Memory and get a pointer to its first element
-        let memory = self.context.i8_type().array_type(30000);
-        let memory_global = module.add_global(memory.const_zero().get_type(), None, "memory");
-        memory_global.set_initializer(&memory.const_zero());
-
-        // Function Header Setup
-        let main_fn_type = self.context.i8_type().fn_type(&[], false);
+        // Function Header Setup, main returns 1 when the runtime reported an error
+        let main_fn_type = self.context.i32_type().fn_type(&[], false);
         let main_fn = module.add_function("main", main_fn_type, Some(Linkage::External));
         let basic_block = self.context.append_basic_block(main_fn, "entry");
         builder.position_at_end(basic_block);
@@ -92,30 +507,533 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
             .build_alloca(self.context.ptr_type(AddressSpace::default()), "ptr")
             .unwrap();
 
+        let mut ircode = IRCodegen::from(self.ast);
+        ircode.funcs.insert("putchar".to_string(), putchar_fn);
+        ircode.funcs.insert("getchar".to_string(), getchar_fn);
+        ircode.funcs.insert("main".to_string(), main_fn);
+
+        if let Some(debug_source) = &self.debug_source {
+            ircode.debug_info = Some(self.build_debug_info(
+                &module,
+                &builder,
+                main_fn,
+                ptr,
+                target_data,
+                debug_source,
+            ));
+        }
+
+        if !self.unbuffered {
+            self.build_flush_fn(&module, target_data, &mut ircode);
+        }
+
+        // Setup Memory holding the initial tape contents
+        let memory = if self.tape.contents.is_empty() {
+            self.context
+                .i8_type()
+                .array_type(self.tape.len as u32)
+                .const_zero()
+        } else {
+            self.context.const_string(&self.tape.initial_cells(), false)
+        };
+        let memory_global = module.add_global(memory.get_type(), None, "memory");
+        memory_global.set_initializer(&memory);
+        ircode.globals.insert("memory".to_string(), memory_global);
+        ircode.tape = self.tape.clone();
+        ircode.circular = self.circular;
+        ircode.checked = self.checked && self.growable.is_none() && !self.circular && !self.guarded;
+        let guarded = self.guarded && self.growable.is_none() && !self.circular;
+
+        if ircode.checked || self.growable.is_some() || guarded {
+            ircode.error_block = Some(self.context.append_basic_block(main_fn, "error"));
+        }
+
+        if ircode.checked {
+            let error_fn_type = self.context.void_type().fn_type(
+                &[
+                    self.context.i64_type().into(),
+                    self.context.i64_type().into(),
+                ],
+                false,
+            );
+            let error_fn =
+                module.add_function("bf_bounds_error", error_fn_type, Some(Linkage::External));
+            ircode.funcs.insert("bf_bounds_error".to_string(), error_fn);
+        }
+
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        if self.growable.is_some() || self.dump || self.guarded {
+            // Tape bounds live in a struct bf_tape so the runtime can move them off of memory
+            let memory_end = unsafe {
+                memory_global.as_pointer_value().const_gep(
+                    self.context.i8_type(),
+                    &[self
+                        .context
+                        .i64_type()
+                        .const_int(self.tape.len as u64, false)],
+                )
+            };
+            let tape = self.context.const_struct(
+                &[
+                    memory_global.as_pointer_value().into(),
+                    memory_end.into(),
+                    self.context.i64_type().const_zero().into(),
+                    self.context.i64_type().const_zero().into(),
+                ],
+                false,
+            );
+            let tape_global = module.add_global(Self::tape_type(&self.context), None, "tape");
+            tape_global.set_linkage(Linkage::Internal);
+            tape_global.set_initializer(&tape);
+            ircode.globals.insert("tape".to_string(), tape_global);
+
+            // Starts every run from the initial bounds, a JitProgram calls main more than once
+            builder
+                .build_store(tape_global.as_pointer_value(), tape)
+                .unwrap();
+        }
+
+        if let Some(cap) = self.growable {
+            let grow_fn_type = ptr_type.fn_type(
+                &[
+                    ptr_type.into(),
+                    ptr_type.into(),
+                    self.context.i64_type().into(),
+                ],
+                false,
+            );
+            let grow_fn =
+                module.add_function("bf_tape_grow", grow_fn_type, Some(Linkage::External));
+            ircode.funcs.insert("bf_tape_grow".to_string(), grow_fn);
+            ircode.growable = Some(cap);
+
+            let free_fn_type = self.context.void_type().fn_type(&[ptr_type.into()], false);
+            let free_fn =
+                module.add_function("bf_tape_free", free_fn_type, Some(Linkage::External));
+            ircode.funcs.insert("bf_tape_free".to_string(), free_fn);
+        }
+
+        let mut tape_start = memory_global.as_pointer_value();
+        if guarded {
+            let guard_fn_type = self.context.i32_type().fn_type(&[ptr_type.into()], false);
+            let guard_fn =
+                module.add_function("bf_guard_tape", guard_fn_type, Some(Linkage::External));
+
+            // bf_guard_tape copies memory into the guarded mapping and points tape at it
+            let tape = ircode.globals.get("tape").unwrap().as_pointer_value();
+            let status = builder
+                .build_call(guard_fn, &[tape.into()], "guard_tape_call")
+                .unwrap()
+                .try_as_basic_value()
+                .left()
+                .unwrap()
+                .into_int_value();
+            let failed = builder
+                .build_int_compare(
+                    IntPredicate::NE,
+                    status,
+                    self.context.i32_type().const_zero(),
+                    "guard_failed",
+                )
+                .unwrap();
+            let guarded_block = self.context.append_basic_block(main_fn, "guarded");
+            builder
+                .build_conditional_branch(failed, ircode.error_block.unwrap(), guarded_block)
+                .unwrap();
+            builder.position_at_end(guarded_block);
+            let start_ptr = builder
+                .build_struct_gep(Self::tape_type(&self.context), tape, 0, "tape_start_gep")
+                .unwrap();
+            tape_start = builder
+                .build_load(ptr_type, start_ptr, "tape_start_load")
+                .unwrap()
+                .into_pointer_value();
+        }
+
+        // Point at the start cell
         let mem_ptr = unsafe {
             builder
                 .build_gep(
-                    self.context.ptr_type(AddressSpace::default()),
-                    memory_global.as_pointer_value(),
-                    &[self.context.i8_type().const_zero()],
+                    self.context.i8_type(),
+                    tape_start,
+                    &[self
+                        .context
+                        .i64_type()
+                        .const_int(self.tape.start as u64, false)],
                     "mem_ptr_gep",
                 )
                 .expect("unable to get pointer to memory")
         };
         builder.build_store(ptr, mem_ptr).unwrap();
 
-        let mut ircode = IRCodegen::from(self.ast);
-        ircode.funcs.insert("putchar".to_string(), putchar_fn);
-        ircode.funcs.insert("getchar".to_string(), getchar_fn);
-        ircode.funcs.insert("main".to_string(), main_fn);
-
         ircode.build(&self.context, &builder, &ptr, ircode.ast);
 
-        let ret = self.context.i8_type().const_zero();
+        if let Some(flush_fn) = ircode.funcs.get("bf_flush") {
+            builder.build_call(*flush_fn, &[], "flush_call").unwrap();
+        }
+
+        if self.dump {
+            let dump_fn_type = self
+                .context
+                .void_type()
+                .fn_type(&[ptr_type.into(), ptr_type.into()], false);
+            let dump_fn =
+                module.add_function("bf_tape_dump", dump_fn_type, Some(Linkage::External));
 
+            let mem_ptr = builder.build_load(ptr_type, ptr, "ptr_load").unwrap();
+            builder
+                .build_call(
+                    dump_fn,
+                    &[
+                        ircode
+                            .globals
+                            .get("tape")
+                            .unwrap()
+                            .as_pointer_value()
+                            .into(),
+                        mem_ptr.into(),
+                    ],
+                    "tape_dump_call",
+                )
+                .unwrap();
+        }
+
+        if guarded {
+            // bf_unguard_tape copies the cells back into memory and releases the mapping
+            let unguard_fn_type = self.context.void_type().fn_type(&[ptr_type.into()], false);
+            let unguard_fn =
+                module.add_function("bf_unguard_tape", unguard_fn_type, Some(Linkage::External));
+            let tape = ircode.globals.get("tape").unwrap().as_pointer_value();
+            builder
+                .build_call(unguard_fn, &[tape.into()], "unguard_tape_call")
+                .unwrap();
+        }
+
+        ircode.build_tape_free(&builder);
+        let ret = self.context.i32_type().const_zero();
         builder.build_return(Some(&ret)).unwrap();
 
+        // The runtime already reported the error and nothing was moved into a guarded mapping
+        // yet when bf_guard_tape failed, so only a grown tape needs releasing
+        if let Some(error_block) = ircode.error_block {
+            builder.position_at_end(error_block);
+            ircode.build_tape_free(&builder);
+            let ret = self.context.i32_type().const_int(1, false);
+            builder.build_return(Some(&ret)).unwrap();
+        }
+
+        if let Some(debug) = &ircode.debug_info {
+            debug.builder.finalize();
+        }
+
+        module
+    }
+
+    // Machine code generator for triple. The cpu and features only apply to ahead of time
+    // compilation, the JIT runs on the host and always targets the host cpu
+    fn target_machine(&self, triple: &TargetTriple, jit: bool) -> anyhow::Result<TargetMachine> {
+        let config = InitializationConfig::default();
+        Target::initialize_native(&config)
+            .map_err(|e| anyhow!("unable to initialize native target: {}", e))?;
+        Target::initialize_x86(&config);
+        Target::initialize_aarch64(&config);
+        Target::initialize_riscv(&config);
+        Target::initialize_webassembly(&config);
+
+        let target = Target::from_triple(triple)
+            .map_err(|e| anyhow!("unsupported target {}: {}", triple, e))?;
+        let host = *triple == TargetMachine::get_default_triple();
+        let cpu = match (&self.cpu, host) {
+            (Some(cpu), _) if !jit => cpu.clone(),
+            (_, true) => TargetMachine::get_host_cpu_name().to_string(),
+            (_, false) => "generic".to_string(),
+        };
+        let features = match (&self.features, host) {
+            (Some(features), _) if !jit => features.clone(),
+            (_, true) => TargetMachine::get_host_cpu_features().to_string(),
+            (_, false) => String::new(),
+        };
+        target
+            .create_target_machine(
+                triple,
+                &cpu,
+                &features,
+                self.opt_level,
+                // Position independent so the object links into PIE executables
+                RelocMode::PIC,
+                CodeModel::Default,
+            )
+            .ok_or_else(|| anyhow!("unable to create target machine for {}", triple))
+    }
+
+    // Targets module at machine and optimizes it in place according to opt_level
+    fn optimize_module(&self, module: &Module, machine: &TargetMachine) -> anyhow::Result<()> {
+        let triple = machine.get_triple();
+        let triple = &triple;
+        module.set_triple(triple);
+        module.set_data_layout(&machine.get_target_data().get_data_layout());
+
+        if self.opt_level == OptimizationLevel::None {
+            return Ok(());
+        }
+
+        // The pointer alloca is promoted first so the later passes see plain SSA values
+        // and can fold runs of +- and <> and hoist the pointer out of loops
+        let level = self.opt_level as u32;
+        let passes = format!(
+            "function(mem2reg,instcombine,gvn,simplifycfg,loop-mssa(licm),loop(indvars,loop-deletion)),default<O{}>",
+            level
+        );
+
+        let options = PassBuilderOptions::create();
+        options.set_loop_vectorization(level >= 2);
+        options.set_loop_slp_vectorization(level >= 2);
+        options.set_loop_unrolling(level >= 2);
+        options.set_merge_functions(level >= 3);
+
         module
+            .run_passes(&passes, machine, options)
+            .map_err(|e| anyhow!("error running optimization passes: {}", e))
+    }
+
+    // Defines bf_flush, which writes the out_buf global to stdout with write(2) and empties it
+    fn build_flush_fn<'s>(
+        &'s self,
+        module: &Module<'s>,
+        target_data: &TargetData,
+        ircode: &mut IRCodegen<'_, 's>,
+    ) {
+        let context = &self.context;
+        let builder = context.create_builder();
+        // size_t and ssize_t of write(2)
+        let size_type = context.ptr_sized_int_type(target_data, None);
+        let ptr_type = context.ptr_type(AddressSpace::default());
+
+        let buf_type = context.i8_type().array_type(OUTPUT_BUFFER as u32);
+        let buf_global = module.add_global(buf_type, None, "out_buf");
+        buf_global.set_linkage(Linkage::Internal);
+        buf_global.set_initializer(&buf_type.const_zero());
+        let len_global = module.add_global(size_type, None, "out_len");
+        len_global.set_linkage(Linkage::Internal);
+        len_global.set_initializer(&size_type.const_zero());
+
+        let write_fn_type = size_type.fn_type(
+            &[context.i32_type().into(), ptr_type.into(), size_type.into()],
+            false,
+        );
+        let write_fn = module.add_function("write", write_fn_type, Some(Linkage::External));
+
+        let flush_fn_type = context.void_type().fn_type(&[], false);
+        let flush_fn = module.add_function("bf_flush", flush_fn_type, Some(Linkage::Internal));
+        let entry_block = context.append_basic_block(flush_fn, "entry");
+        let loop_block = context.append_basic_block(flush_fn, "flush_loop");
+        let write_block = context.append_basic_block(flush_fn, "flush_write");
+        let next_block = context.append_basic_block(flush_fn, "flush_next");
+        let done_block = context.append_basic_block(flush_fn, "flush_done");
+
+        builder.position_at_end(entry_block);
+        let len = builder
+            .build_load(size_type, len_global.as_pointer_value(), "out_len_load")
+            .unwrap()
+            .into_int_value();
+        builder.build_unconditional_branch(loop_block).unwrap();
+
+        // Retries short writes, gives up on errors since there is nowhere to report them
+        builder.position_at_end(loop_block);
+        let written = builder.build_phi(size_type, "written").unwrap();
+        let written_value = written.as_basic_value().into_int_value();
+        let remaining = builder
+            .build_int_compare(IntPredicate::ULT, written_value, len, "flush_remaining")
+            .unwrap();
+        builder
+            .build_conditional_branch(remaining, write_block, done_block)
+            .unwrap();
+
+        builder.position_at_end(write_block);
+        let chunk = unsafe {
+            builder
+                .build_gep(
+                    context.i8_type(),
+                    buf_global.as_pointer_value(),
+                    &[written_value],
+                    "flush_chunk",
+                )
+                .unwrap()
+        };
+        let count = builder
+            .build_int_sub(len, written_value, "flush_count")
+            .unwrap();
+        let result = builder
+            .build_call(
+                write_fn,
+                &[
+                    context.i32_type().const_int(1, false).into(),
+                    chunk.into(),
+                    count.into(),
+                ],
+                "write_call",
+            )
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        let ok = builder
+            .build_int_compare(
+                IntPredicate::SGT,
+                result,
+                size_type.const_zero(),
+                "write_ok",
+            )
+            .unwrap();
+        builder
+            .build_conditional_branch(ok, next_block, done_block)
+            .unwrap();
+
+        builder.position_at_end(next_block);
+        let next = builder
+            .build_int_add(written_value, result, "written_next")
+            .unwrap();
+        builder.build_unconditional_branch(loop_block).unwrap();
+        written.add_incoming(&[(&size_type.const_zero(), entry_block), (&next, next_block)]);
+
+        builder.position_at_end(done_block);
+        builder
+            .build_store(len_global.as_pointer_value(), size_type.const_zero())
+            .unwrap();
+        builder.build_return(None).unwrap();
+
+        ircode.funcs.insert("bf_flush".to_string(), flush_fn);
+        ircode.globals.insert("out_buf".to_string(), buf_global);
+        ircode.globals.insert("out_len".to_string(), len_global);
+    }
+
+    // Describes main and its ptr variable, instructions built before the first operator
+    // are attributed to the start of the file
+    fn build_debug_info<'m>(
+        &self,
+        module: &Module<'m>,
+        builder: &Builder<'m>,
+        main_fn: FunctionValue<'m>,
+        ptr: PointerValue<'m>,
+        target_data: &TargetData,
+        (path, source): &(PathBuf, &[u8]),
+    ) -> DebugInfo<'m> {
+        let context = module.get_context();
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            context
+                .i32_type()
+                .const_int(debug_metadata_version() as u64, false),
+        );
+
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        let directory = path.parent().unwrap_or(Path::new(".")).to_string_lossy();
+        let (dibuilder, compile_unit) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &filename,
+            &directory,
+            "brainfuck-rs",
+            self.opt_level != OptimizationLevel::None,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        let file = compile_unit.get_file();
+
+        let main_type = dibuilder.create_subroutine_type(file, None, &[], DIFlags::ZERO);
+        let subprogram = dibuilder.create_function(
+            compile_unit.as_debug_info_scope(),
+            "main",
+            None,
+            file,
+            1,
+            main_type,
+            false,
+            true,
+            1,
+            DIFlags::ZERO,
+            self.opt_level != OptimizationLevel::None,
+        );
+        main_fn.set_subprogram(subprogram);
+        let scope = subprogram.as_debug_info_scope();
+
+        let location = dibuilder.create_debug_location(context, 1, 1, scope, None);
+        builder.set_current_debug_location(location);
+
+        // DW_ATE_unsigned_char
+        let cell_type = dibuilder
+            .create_basic_type("cell", 8, 0x08, DIFlags::ZERO)
+            .unwrap();
+        let ptr_type = dibuilder.create_pointer_type(
+            "cell*",
+            cell_type.as_type(),
+            target_data.get_pointer_byte_size(None) as u64 * 8,
+            0,
+            AddressSpace::default(),
+        );
+        let ptr_var = dibuilder.create_auto_variable(
+            scope,
+            "ptr",
+            file,
+            1,
+            ptr_type.as_type(),
+            true,
+            DIFlags::ZERO,
+            0,
+        );
+        dibuilder.insert_declare_at_end(
+            ptr,
+            Some(ptr_var),
+            None,
+            location,
+            main_fn.get_first_basic_block().unwrap(),
+        );
+
+        let mut positions = Vec::with_capacity(source.len());
+        let (mut line, mut column) = (1, 1);
+        for c in source.iter() {
+            positions.push((line, column));
+            match c {
+                b'\n' => (line, column) = (line + 1, 1),
+                _ => column += 1,
+            }
+        }
+
+        DebugInfo {
+            builder: dibuilder,
+            scope,
+            positions,
+        }
+    }
+
+    // Whether the generated code calls into runtime/tape.c
+    fn needs_runtime(&self) -> bool {
+        self.growable.is_some() || self.dump || self.checked || self.guarded
+    }
+
+    // Matches struct bf_tape in runtime/tape.c: start, end, origin, owned
+    fn tape_type(context: &Context) -> StructType<'_> {
+        let ptr_type = context.ptr_type(AddressSpace::default());
+        let i64_type = context.i64_type();
+        context.struct_type(
+            &[
+                ptr_type.into(),
+                ptr_type.into(),
+                i64_type.into(),
+                i64_type.into(),
+            ],
+            false,
+        )
     }
 
     fn build(
@@ -125,15 +1043,35 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
         ptr: &'a PointerValue<'a>,
         ast: &'a Ast,
     ) {
-        for op in ast.inner() {
+        for (source, op) in ast.iter() {
+            if let Some(debug) = &self.debug_info {
+                let (line, column) = debug.positions[source];
+                let location =
+                    debug
+                        .builder
+                        .create_debug_location(context, line, column, debug.scope, None);
+                builder.set_current_debug_location(location);
+            }
             match op {
                 Operator::IncPtr => self.build_move(context, builder, ptr, 1),
                 Operator::DecPtr => self.build_move(context, builder, ptr, -1),
-                Operator::Inc => self.build_inc(context, builder, ptr),
-                Operator::Dec => self.build_dec(context, builder, ptr),
-                Operator::In => self.build_in(context, builder, ptr),
-                Operator::Out => self.build_out(context, builder, ptr),
-                Operator::Loop(ast) => self.build_loop(context, builder, ptr, ast),
+                Operator::Inc => {
+                    self.build_cell_check(context, builder, ptr, source);
+                    self.build_inc(context, builder, ptr)
+                }
+                Operator::Dec => {
+                    self.build_cell_check(context, builder, ptr, source);
+                    self.build_dec(context, builder, ptr)
+                }
+                Operator::In => {
+                    self.build_cell_check(context, builder, ptr, source);
+                    self.build_in(context, builder, ptr)
+                }
+                Operator::Out => {
+                    self.build_cell_check(context, builder, ptr, source);
+                    self.build_out(context, builder, ptr)
+                }
+                Operator::Loop(ast) => self.build_loop(context, builder, ptr, ast, source),
             }
         }
     }
@@ -145,6 +1083,7 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
         builder: &'a Builder,
         ptr: &'a PointerValue,
         ast: &'a Ast,
+        source: usize,
     ) {
         let start_block =
             context.append_basic_block(*self.funcs.get("main").unwrap(), "loop_start");
@@ -154,6 +1093,7 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
         builder.build_unconditional_branch(start_block).unwrap();
         builder.position_at_end(start_block);
 
+        self.build_cell_check(context, builder, ptr, source);
         let mem_ptr = builder
             .build_load(context.ptr_type(AddressSpace::default()), *ptr, "ptr_load")
             .unwrap()
@@ -199,16 +1139,243 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
         let mem_ptr = unsafe {
             builder
                 .build_gep(
-                    context.ptr_type(AddressSpace::default()),
+                    context.i8_type(),
                     mem_ptr,
-                    &[context.i8_type().const_int(offset as u64, false)],
+                    &[context.i64_type().const_int(offset as u64, false)],
                     "mem_ptr_gep",
                 )
                 .unwrap()
         };
+
+        if self.circular {
+            self.build_wrap(context, builder, ptr, mem_ptr);
+            return;
+        }
+        builder.build_store(*ptr, mem_ptr).unwrap();
+
+        if self.library_params.is_some() {
+            self.build_library_bounds_check(context, builder, mem_ptr);
+            return;
+        }
+
+        if let Some(cap) = self.growable {
+            self.build_grow_check(context, builder, ptr, mem_ptr, cap);
+        }
+    }
+
+    // Bounds checks the current cell before the instruction at source accesses it
+    fn build_cell_check(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+        source: usize,
+    ) {
+        if !self.checked {
+            return;
+        }
+        let mem_ptr = builder
+            .build_load(context.ptr_type(AddressSpace::default()), *ptr, "ptr_load")
+            .unwrap()
+            .into_pointer_value();
+        self.build_bounds_check(context, builder, mem_ptr, source);
+    }
+
+    // Calls bf_bounds_error with the source offset and cell index when mem_ptr left memory
+    // and returns from main through the error block
+    fn build_bounds_check(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        mem_ptr: PointerValue,
+        source: usize,
+    ) {
+        let main_fn = *self.funcs.get("main").unwrap();
+        let error_block = context.append_basic_block(main_fn, "bounds_error");
+        let cont_block = context.append_basic_block(main_fn, "bounds_ok");
+
+        let i64_type = context.i64_type();
+        let memory = self.globals.get("memory").unwrap().as_pointer_value();
+        let memory_end = unsafe {
+            memory.const_gep(
+                context.i8_type(),
+                &[i64_type.const_int(self.tape.len as u64, false)],
+            )
+        };
+
+        let below = builder
+            .build_int_compare(IntPredicate::ULT, mem_ptr, memory, "bounds_below")
+            .unwrap();
+        let above = builder
+            .build_int_compare(IntPredicate::UGE, mem_ptr, memory_end, "bounds_above")
+            .unwrap();
+        let outside = builder.build_or(below, above, "bounds_outside").unwrap();
+
+        builder
+            .build_conditional_branch(outside, error_block, cont_block)
+            .unwrap();
+        builder.position_at_end(error_block);
+
+        let base = builder
+            .build_ptr_to_int(memory, i64_type, "memory_int")
+            .unwrap();
+        let cur = builder
+            .build_ptr_to_int(mem_ptr, i64_type, "mem_ptr_int")
+            .unwrap();
+        let cell = builder.build_int_sub(cur, base, "bounds_cell").unwrap();
+        if let Some(flush_fn) = self.funcs.get("bf_flush") {
+            builder.build_call(*flush_fn, &[], "flush_call").unwrap();
+        }
+        builder
+            .build_call(
+                *self.funcs.get("bf_bounds_error").unwrap(),
+                &[i64_type.const_int(source as u64, false).into(), cell.into()],
+                "bounds_error_call",
+            )
+            .unwrap();
+        builder
+            .build_unconditional_branch(self.error_block.unwrap())
+            .unwrap();
+
+        builder.position_at_end(cont_block);
+    }
+
+    // Stores mem_ptr into ptr after wrapping its index into memory modulo the tape length
+    fn build_wrap(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+        mem_ptr: PointerValue,
+    ) {
+        let i64_type = context.i64_type();
+        let memory = self.globals.get("memory").unwrap().as_pointer_value();
+        let len = i64_type.const_int(self.tape.len as u64, false);
+
+        let base = builder
+            .build_ptr_to_int(memory, i64_type, "memory_int")
+            .unwrap();
+        let cur = builder
+            .build_ptr_to_int(mem_ptr, i64_type, "mem_ptr_int")
+            .unwrap();
+        let idx = builder.build_int_sub(cur, base, "tape_idx").unwrap();
+
+        let rem = builder.build_int_signed_rem(idx, len, "tape_rem").unwrap();
+        let negative = builder
+            .build_int_compare(IntPredicate::SLT, rem, i64_type.const_zero(), "tape_neg")
+            .unwrap();
+        let adjusted = builder.build_int_add(rem, len, "tape_adj").unwrap();
+        let idx = builder
+            .build_select(negative, adjusted, rem, "tape_wrap")
+            .unwrap()
+            .into_int_value();
+
+        let mem_ptr = unsafe {
+            builder
+                .build_gep(context.i8_type(), memory, &[idx], "mem_ptr_gep")
+                .unwrap()
+        };
         builder.build_store(*ptr, mem_ptr).unwrap();
     }
 
+    // Calls bf_tape_grow when the pointer left the allocated tape
+    fn build_grow_check(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+        mem_ptr: PointerValue,
+        cap: u64,
+    ) {
+        let main_fn = *self.funcs.get("main").unwrap();
+        let grow_block = context.append_basic_block(main_fn, "tape_grow");
+        let cont_block = context.append_basic_block(main_fn, "tape_cont");
+
+        let ptr_type = context.ptr_type(AddressSpace::default());
+        let tape = self.globals.get("tape").unwrap().as_pointer_value();
+        let start_ptr = builder
+            .build_struct_gep(Self::tape_type(context), tape, 0, "tape_start_gep")
+            .unwrap();
+        let start = builder
+            .build_load(ptr_type, start_ptr, "tape_start_load")
+            .unwrap()
+            .into_pointer_value();
+        let end_ptr = builder
+            .build_struct_gep(Self::tape_type(context), tape, 1, "tape_end_gep")
+            .unwrap();
+        let end = builder
+            .build_load(ptr_type, end_ptr, "tape_end_load")
+            .unwrap()
+            .into_pointer_value();
+
+        let below = builder
+            .build_int_compare(IntPredicate::ULT, mem_ptr, start, "tape_below")
+            .unwrap();
+        let above = builder
+            .build_int_compare(IntPredicate::UGE, mem_ptr, end, "tape_above")
+            .unwrap();
+        let outside = builder.build_or(below, above, "tape_outside").unwrap();
+
+        builder
+            .build_conditional_branch(outside, grow_block, cont_block)
+            .unwrap();
+        builder.position_at_end(grow_block);
+
+        self.build_grow_call(context, builder, ptr, mem_ptr, cap);
+
+        builder.build_unconditional_branch(cont_block).unwrap();
+        builder.position_at_end(cont_block);
+    }
+
+    // Stores the pointer returned by bf_tape_grow for mem_ptr into ptr, or returns from main
+    // through the error block when it failed
+    fn build_grow_call(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+        mem_ptr: PointerValue,
+        cap: u64,
+    ) {
+        // bf_tape_grow reports hitting the memory cap itself, after the output so far
+        if let Some(flush_fn) = self.funcs.get("bf_flush") {
+            builder.build_call(*flush_fn, &[], "flush_call").unwrap();
+        }
+        let mem_ptr = builder
+            .build_call(
+                *self.funcs.get("bf_tape_grow").unwrap(),
+                &[
+                    mem_ptr.into(),
+                    self.globals.get("tape").unwrap().as_pointer_value().into(),
+                    context.i64_type().const_int(cap, false).into(),
+                ],
+                "tape_grow_call",
+            )
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+
+        let grown_block = context.append_basic_block(*self.funcs.get("main").unwrap(), "grown");
+        let failed = builder.build_is_null(mem_ptr, "tape_grow_failed").unwrap();
+        builder
+            .build_conditional_branch(failed, self.error_block.unwrap(), grown_block)
+            .unwrap();
+        builder.position_at_end(grown_block);
+        builder.build_store(*ptr, mem_ptr).unwrap();
+    }
+
+    // Calls bf_tape_free when the tape may have been grown
+    fn build_tape_free(&self, builder: &'a Builder) {
+        if let Some(free_fn) = self.funcs.get("bf_tape_free") {
+            let tape = self.globals.get("tape").unwrap().as_pointer_value();
+            builder
+                .build_call(*free_fn, &[tape.into()], "tape_free_call")
+                .unwrap();
+        }
+    }
+
     // Implements +
     fn build_inc(&self, context: &'a Context, builder: &'a Builder, ptr: &'a PointerValue) {
         let mem_ptr = builder
@@ -258,6 +1425,15 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
             .unwrap()
             .into_int_value();
 
+        if let Some(flush_fn) = self.funcs.get("bf_flush") {
+            self.build_buffered_out(context, builder, value, *flush_fn);
+            return;
+        }
+        if self.library_params.is_some() {
+            self.build_library_out(context, builder, value);
+            return;
+        }
+
         let s = builder
             .build_int_s_extend(value, context.i32_type(), "putchar s extend")
             .unwrap();
@@ -269,7 +1445,67 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
             .unwrap();
     }
 
+    // Appends value to out_buf and flushes it once it is full
+    fn build_buffered_out(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        value: IntValue,
+        flush_fn: FunctionValue,
+    ) {
+        let main_fn = *self.funcs.get("main").unwrap();
+        let flush_block = context.append_basic_block(main_fn, "out_flush");
+        let cont_block = context.append_basic_block(main_fn, "out_cont");
+
+        let buf = self.globals.get("out_buf").unwrap().as_pointer_value();
+        let len_global = self.globals.get("out_len").unwrap();
+        let len_ptr = len_global.as_pointer_value();
+        // Pointer sized like the global defined by build_flush_fn
+        let size_type = len_global.get_value_type().into_int_type();
+
+        let len = builder
+            .build_load(size_type, len_ptr, "out_len_load")
+            .unwrap()
+            .into_int_value();
+        let slot = unsafe {
+            builder
+                .build_gep(context.i8_type(), buf, &[len], "out_slot")
+                .unwrap()
+        };
+        builder.build_store(slot, value).unwrap();
+        let len = builder
+            .build_int_add(len, size_type.const_int(1, false), "out_len_inc")
+            .unwrap();
+        builder.build_store(len_ptr, len).unwrap();
+
+        let full = builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                len,
+                size_type.const_int(OUTPUT_BUFFER, false),
+                "out_full",
+            )
+            .unwrap();
+        builder
+            .build_conditional_branch(full, flush_block, cont_block)
+            .unwrap();
+        builder.position_at_end(flush_block);
+        builder.build_call(flush_fn, &[], "flush_call").unwrap();
+        builder.build_unconditional_branch(cont_block).unwrap();
+        builder.position_at_end(cont_block);
+    }
+
     fn build_in(&self, context: &'a Context, builder: &'a Builder, ptr: &'a PointerValue) {
+        if self.library_params.is_some() {
+            self.build_library_in(context, builder, ptr);
+            return;
+        }
+
+        // Prompts have to be visible before the program blocks on input
+        if let Some(flush_fn) = self.funcs.get("bf_flush") {
+            builder.build_call(*flush_fn, &[], "flush_call").unwrap();
+        }
+
         let getchar_call = builder
             .build_call(*self.funcs.get("getchar").unwrap(), &[], "getchar call")
             .unwrap();
@@ -280,15 +1516,83 @@ impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
             .unwrap()
             .into_int_value();
 
-        let truncated = builder
-            .build_int_truncate(getchar, context.i8_type(), "getchar truncate")
-            .unwrap();
+        self.build_store_input(context, builder, ptr, getchar);
+    }
 
+    // Stores what getchar or the library's read callback returned into the current cell,
+    // negative values mean the end of input and store what eof asks for instead
+    fn build_store_input(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+        input: IntValue,
+    ) {
         let mem_ptr = builder
             .build_load(context.ptr_type(AddressSpace::default()), *ptr, "ptr_load")
             .unwrap()
             .into_pointer_value();
+        let truncated = builder
+            .build_int_truncate(input, context.i8_type(), "input_truncate")
+            .unwrap();
+
+        let at_end = builder
+            .build_int_compare(
+                IntPredicate::SLT,
+                input,
+                input.get_type().const_zero(),
+                "input_at_end",
+            )
+            .unwrap();
+        let eof = match self.eof {
+            Eof::MinusOne => context.i8_type().const_all_ones(),
+            Eof::Zero => context.i8_type().const_zero(),
+            Eof::Unchanged => builder
+                .build_load(context.i8_type(), mem_ptr, "mem_ptr_load")
+                .unwrap()
+                .into_int_value(),
+        };
+        let value = builder
+            .build_select(at_end, eof, truncated, "input_select")
+            .unwrap()
+            .into_int_value();
+        builder.build_store(mem_ptr, value).unwrap();
+    }
+}
+
+// Replaces lib with an archive holding object using $AR or ar
+fn archive(object: &Path, lib: &Path) -> anyhow::Result<()> {
+    let ar = std::env::var("AR").unwrap_or_else(|_| "ar".to_string());
+    // ar adds to existing archives instead of replacing them
+    let _ = std::fs::remove_file(lib);
+
+    let status = match Command::new(&ar).arg("rcs").arg(lib).arg(object).status() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => bail!(
+            "archiver {} not found, install binutils, set AR or emit an object instead",
+            ar
+        ),
+        status => status.with_context(|| format!("unable to run archiver {}", ar))?,
+    };
+    if !status.success() {
+        bail!("archiver {} failed with {}", ar, status);
+    }
+    Ok(())
+}
+
+// Private directory for intermediate files, deleted when the result is dropped
+fn temp_dir() -> anyhow::Result<tempfile::TempDir> {
+    tempfile::Builder::new()
+        .prefix("brainfuck-rs")
+        .tempdir()
+        .context("unable to create a temporary directory")
+}
 
-        builder.build_store(mem_ptr, truncated).unwrap();
+fn write_artifact(path: Option<&Path>, bytes: &[u8]) -> anyhow::Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, bytes)
+            .with_context(|| format!("unable to write {}", path.display())),
+        None => io::stdout()
+            .write_all(bytes)
+            .context("unable to write to stdout"),
     }
 }
@@ -0,0 +1,101 @@
+use super::IRCodegen;
+use crate::dump::TapeSnapshot;
+use crate::runtime;
+use anyhow::{anyhow, bail};
+use inkwell::execution_engine::JitFunction;
+use inkwell::AddressSpace;
+use std::io::{Read, Write};
+
+type MainFn = unsafe extern "C" fn() -> i32;
+type MemoryFn = unsafe extern "C" fn() -> *mut u8;
+
+/// Machine code built once by IRCodegen::jit_program and run as often as needed. Every run
+/// reads from and writes to the streams it is given, output is flushed when the run ends
+pub struct JitProgram<'ctx> {
+    main: JitFunction<'ctx, MainFn>,
+    // The memory global inside the JIT's data section
+    memory: *mut u8,
+    initial: Vec<u8>,
+}
+
+impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
+    /// Compiles the program without running it, the result borrows the codegen's Context
+    pub fn jit_program(&self) -> anyhow::Result<JitProgram<'_>> {
+        let machine = self.host_machine()?;
+        let module = self.build_module(&machine.get_target_data());
+
+        // Lets the host reset memory between runs without a symbol lookup API
+        let memory = module.get_global("memory").unwrap();
+        let memory_fn_type = self
+            .context
+            .ptr_type(AddressSpace::default())
+            .fn_type(&[], false);
+        let memory_fn = module.add_function("bf_memory", memory_fn_type, None);
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(memory_fn, "entry"));
+        builder
+            .build_return(Some(&memory.as_pointer_value()))
+            .unwrap();
+
+        let exec_engine = self.build_engine(&module, &machine, true)?;
+        unsafe {
+            let memory_fn: JitFunction<MemoryFn> = exec_engine
+                .get_function("bf_memory")
+                .map_err(|e| anyhow!("unable to look up bf_memory: {}", e))?;
+            Ok(JitProgram {
+                main: exec_engine
+                    .get_function("main")
+                    .map_err(|e| anyhow!("unable to look up main: {}", e))?,
+                memory: memory_fn.call(),
+                initial: self.tape.initial_cells(),
+            })
+        }
+    }
+}
+
+impl JitProgram<'_> {
+    /// Runs on a fresh copy of the configured initial tape
+    pub fn run(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<TapeSnapshot>> {
+        let cells = unsafe { std::slice::from_raw_parts_mut(self.memory, self.initial.len()) };
+        cells.copy_from_slice(&self.initial);
+        self.call(input, output)
+    }
+
+    /// Runs on tape, which must have as many cells as the configured tape, and leaves
+    /// the final cells in it, also when the run fails. Growable tapes move the cells off
+    /// of memory, use dump_tape to get their final state from the returned snapshot instead
+    pub fn run_on(
+        &mut self,
+        tape: &mut [u8],
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<TapeSnapshot>> {
+        if tape.len() != self.initial.len() {
+            bail!(
+                "tape has {} cells but the program was compiled for {}",
+                tape.len(),
+                self.initial.len()
+            );
+        }
+
+        let cells = unsafe { std::slice::from_raw_parts_mut(self.memory, self.initial.len()) };
+        cells.copy_from_slice(tape);
+        let snapshot = self.call(input, output);
+        let cells = unsafe { std::slice::from_raw_parts(self.memory, self.initial.len()) };
+        tape.copy_from_slice(cells);
+        snapshot
+    }
+
+    fn call(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<TapeSnapshot>> {
+        let status = runtime::with_host_io(input, output, || unsafe { self.main.call() });
+        runtime::finish(status)
+    }
+}
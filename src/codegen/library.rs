@@ -0,0 +1,220 @@
+use super::IRCodegen;
+use anyhow::bail;
+use inkwell::basic_block::BasicBlock;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::targets::TargetData;
+use inkwell::values::{IntValue, PointerValue};
+use inkwell::{AddressSpace, IntPredicate};
+
+// Arguments of the library function the operators are built against instead of globals
+pub(super) struct LibraryParams<'ctx> {
+    start: PointerValue<'ctx>,
+    end: PointerValue<'ctx>,
+    read: PointerValue<'ctx>,
+    write: PointerValue<'ctx>,
+    ctx: PointerValue<'ctx>,
+    // Returns 1 from the function once the pointer left the tape
+    bounds_error: BasicBlock<'ctx>,
+}
+
+impl<'a, 'ctx> IRCodegen<'a, 'ctx> {
+    // Builds int bf_<name>(uint8_t *tape, size_t len, bf_read_fn read, bf_write_fn write, void *ctx).
+    // Everything lives in the arguments so the function is reentrant
+    pub(super) fn build_library_module(&self, name: &str, target_data: &TargetData) -> Module<'_> {
+        let context = &self.context;
+        let module = context.create_module(name);
+        let builder = context.create_builder();
+        let ptr_type = context.ptr_type(AddressSpace::default());
+        let i32_type = context.i32_type();
+        let size_type = context.ptr_sized_int_type(target_data, None);
+
+        let fn_type = i32_type.fn_type(
+            &[
+                ptr_type.into(),
+                size_type.into(),
+                ptr_type.into(),
+                ptr_type.into(),
+                ptr_type.into(),
+            ],
+            false,
+        );
+        let function =
+            module.add_function(&format!("bf_{}", name), fn_type, Some(Linkage::External));
+        let entry_block = context.append_basic_block(function, "entry");
+        let error_block = context.append_basic_block(function, "bounds_error");
+
+        builder.position_at_end(error_block);
+        builder
+            .build_return(Some(&i32_type.const_int(1, false)))
+            .unwrap();
+
+        builder.position_at_end(entry_block);
+        let params = function.get_params();
+        let start = params[0].into_pointer_value();
+        let len = params[1].into_int_value();
+        let end = unsafe {
+            builder
+                .build_gep(context.i8_type(), start, &[len], "tape_end")
+                .unwrap()
+        };
+        let ptr = builder.build_alloca(ptr_type, "ptr").unwrap();
+        builder.build_store(ptr, start).unwrap();
+
+        // Moves are checked where they happen, but the pointer starts on tape[0] which
+        // only exists when len is not 0
+        let run_block = context.append_basic_block(function, "run");
+        let empty = builder
+            .build_int_compare(IntPredicate::EQ, len, size_type.const_zero(), "tape_empty")
+            .unwrap();
+        builder
+            .build_conditional_branch(empty, error_block, run_block)
+            .unwrap();
+        builder.position_at_end(run_block);
+
+        let mut ircode = IRCodegen::from(self.ast);
+        // build appends its blocks to the function stored as main
+        ircode.funcs.insert("main".to_string(), function);
+        ircode.library_params = Some(LibraryParams {
+            start,
+            end,
+            read: params[2].into_pointer_value(),
+            write: params[3].into_pointer_value(),
+            ctx: params[4].into_pointer_value(),
+            bounds_error: error_block,
+        });
+
+        ircode.build(context, &builder, &ptr, ircode.ast);
+
+        builder.build_return(Some(&i32_type.const_zero())).unwrap();
+
+        module
+    }
+
+    // Leaves the function through bounds_error when mem_ptr is outside of the caller's tape
+    pub(super) fn build_library_bounds_check(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        mem_ptr: PointerValue,
+    ) {
+        let params = self.library_params.as_ref().unwrap();
+        let cont_block = context.append_basic_block(*self.funcs.get("main").unwrap(), "bounds_ok");
+
+        let below = builder
+            .build_int_compare(IntPredicate::ULT, mem_ptr, params.start, "bounds_below")
+            .unwrap();
+        let above = builder
+            .build_int_compare(IntPredicate::UGE, mem_ptr, params.end, "bounds_above")
+            .unwrap();
+        let outside = builder.build_or(below, above, "bounds_outside").unwrap();
+
+        builder
+            .build_conditional_branch(outside, params.bounds_error, cont_block)
+            .unwrap();
+        builder.position_at_end(cont_block);
+    }
+
+    // Calls write(ctx, value) through the caller's function pointer
+    pub(super) fn build_library_out(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        value: IntValue,
+    ) {
+        let params = self.library_params.as_ref().unwrap();
+        let i32_type = context.i32_type();
+        let write_type = i32_type.fn_type(
+            &[
+                context.ptr_type(AddressSpace::default()).into(),
+                i32_type.into(),
+            ],
+            false,
+        );
+
+        let byte = builder
+            .build_int_z_extend(value, i32_type, "write_zext")
+            .unwrap();
+        builder
+            .build_indirect_call(
+                write_type,
+                params.write,
+                &[params.ctx.into(), byte.into()],
+                "write_call",
+            )
+            .unwrap();
+    }
+
+    // Stores read(ctx) into the current cell, a negative value is the end of input and
+    // stores what eof asks for like getchar's EOF does in compiled programs
+    pub(super) fn build_library_in(
+        &self,
+        context: &'a Context,
+        builder: &'a Builder,
+        ptr: &'a PointerValue,
+    ) {
+        let params = self.library_params.as_ref().unwrap();
+        let ptr_type = context.ptr_type(AddressSpace::default());
+        let read_type = context.i32_type().fn_type(&[ptr_type.into()], false);
+
+        let byte = builder
+            .build_indirect_call(read_type, params.read, &[params.ctx.into()], "read_call")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        self.build_store_input(context, builder, ptr, byte);
+    }
+}
+
+/// Library names become part of the C function name bf_<name>
+pub fn validate_identifier(name: &str) -> anyhow::Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("{} is not a valid C identifier", name);
+    }
+    Ok(())
+}
+
+/// C declarations for the function built by build_library_module
+pub fn library_header(name: &str) -> String {
+    let guard = format!("BF_{}_H", name.to_uppercase());
+    format!(
+        r#"// Generated by brainfuck-rs for the library function bf_{name}
+#ifndef {guard}
+#define {guard}
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {{
+#endif
+
+#ifndef BF_CALLBACKS_DEFINED
+#define BF_CALLBACKS_DEFINED
+// Returns the next input byte, or a negative value at the end of input
+typedef int (*bf_read_fn)(void *ctx);
+// Receives every output byte, the return value is ignored
+typedef int (*bf_write_fn)(void *ctx, int byte);
+#endif
+
+// Runs the program on the len cells at tape starting with the pointer on tape[0].
+// Returns 0 when the program finishes and 1 when the pointer leaves the tape,
+// an empty tape returns 1 without running anything
+int bf_{name}(uint8_t *tape, size_t len, bf_read_fn read, bf_write_fn write, void *ctx);
+
+#ifdef __cplusplus
+}}
+#endif
+
+#endif
+"#
+    )
+}
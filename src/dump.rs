@@ -0,0 +1,216 @@
+use crate::tape::Tape;
+use std::io::{self, Write};
+
+// Cells shown on each side of the pointer by the hex dump
+const HEX_WINDOW: isize = 128;
+const HEX_ROW: isize = 16;
+
+/// How TapeSnapshot::write lays out the tape
+#[derive(Clone, Copy)]
+pub enum DumpFormat {
+    /// Rows of 16 cells around the pointer, the current cell is bracketed
+    Hex,
+    /// Every cell as a raw byte, only for tapes without gaps
+    Binary,
+    /// {"first": .., "pointer": .., "cells": [..]}, tapes with gaps list {"first", "cells"} segments
+    Json,
+}
+
+/// Final state of a tape, indices are relative to the leftmost cell of the initial tape
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeSnapshot {
+    /// Cells backed by the tape in ascending order, everything around them reads as zero.
+    /// Only sparse tapes have more than one
+    pub segments: Vec<Segment>,
+    /// Cell the pointer ended on, may lie outside of every segment
+    pub pointer: isize,
+}
+
+/// Contiguous run of cells starting at index first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub first: isize,
+    pub cells: Vec<u8>,
+}
+
+impl TapeSnapshot {
+    /// A tape of contiguous cells starting at index first
+    pub fn new(first: isize, cells: Vec<u8>, pointer: isize) -> Self {
+        Self {
+            segments: vec![Segment { first, cells }],
+            pointer,
+        }
+    }
+
+    /// Copies the tape one segment at a time, so the cells between far apart pages of a
+    /// sparse tape are never materialized
+    pub fn from_tape<T: Tape + ?Sized>(tape: &T, pointer: isize) -> Self {
+        Self {
+            segments: tape
+                .segments()
+                .into_iter()
+                .map(|range| Segment {
+                    first: range.start,
+                    cells: range.map(|i| *tape.get(i).unwrap()).collect(),
+                })
+                .collect(),
+            pointer,
+        }
+    }
+
+    /// Value of the cell at idx, zero outside of the segments
+    pub fn get(&self, idx: isize) -> u8 {
+        let i = self.segments.partition_point(|s| s.first <= idx);
+        i.checked_sub(1)
+            .and_then(|i| {
+                let segment = &self.segments[i];
+                segment.cells.get((idx - segment.first) as usize).copied()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Fails for binary dumps of tapes with more than one segment
+    pub fn write(&self, format: DumpFormat, out: &mut dyn Write) -> io::Result<()> {
+        match format {
+            DumpFormat::Hex => self.write_hex(out),
+            DumpFormat::Binary => match self.segments.as_slice() {
+                [] => Ok(()),
+                [segment] => out.write_all(&segment.cells),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "binary dumps need a tape without gaps, use hex or json for sparse tapes",
+                )),
+            },
+            DumpFormat::Json => self.write_json(out),
+        }
+    }
+
+    fn write_hex(&self, out: &mut dyn Write) -> io::Result<()> {
+        // Stays on the tape unless the pointer itself fell off of it
+        let first = self.segments.first().map_or(self.pointer, |s| s.first);
+        let last = self
+            .segments
+            .last()
+            .map_or(self.pointer, |s| s.first + s.cells.len() as isize - 1);
+        let from = (self.pointer - HEX_WINDOW).max(first.min(self.pointer));
+        let to = (self.pointer + HEX_WINDOW).min(last.max(self.pointer));
+        let start = from.div_euclid(HEX_ROW) * HEX_ROW;
+        let end = to.div_euclid(HEX_ROW) * HEX_ROW + HEX_ROW;
+
+        writeln!(out, "pointer: {}", self.pointer)?;
+        for row in (start..end).step_by(HEX_ROW as usize) {
+            write!(out, "{:>8}:", row)?;
+            for idx in row..row + HEX_ROW {
+                if idx == self.pointer {
+                    write!(out, "[{:02x}]", self.get(idx))?;
+                } else {
+                    write!(out, " {:02x} ", self.get(idx))?;
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self.segments.as_slice() {
+            [segment] => {
+                write!(
+                    out,
+                    "{{\"first\":{},\"pointer\":{},\"cells\":",
+                    segment.first, self.pointer
+                )?;
+                write_cells(out, &segment.cells)?;
+            }
+            segments => {
+                write!(out, "{{\"pointer\":{},\"segments\":[", self.pointer)?;
+                for (i, segment) in segments.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    write!(out, "{{\"first\":{},\"cells\":", segment.first)?;
+                    write_cells(out, &segment.cells)?;
+                    write!(out, "}}")?;
+                }
+                write!(out, "]")?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+fn write_cells(out: &mut dyn Write, cells: &[u8]) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, cell) in cells.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{}", cell)?;
+    }
+    write!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(snapshot: &TapeSnapshot, format: DumpFormat) -> io::Result<String> {
+        let mut out = vec![];
+        snapshot.write(format, &mut out)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    #[test]
+    fn hex_marks_the_pointer() {
+        let snapshot = TapeSnapshot::new(0, vec![1, 2, 0xab], 2);
+        assert_eq!(
+            dump(&snapshot, DumpFormat::Hex).unwrap(),
+            "pointer: 2\n       0: 01  02 [ab] 00  00  00  00  00  00  00  00  00  00  00  00  00 \n"
+        );
+    }
+
+    #[test]
+    fn hex_window_follows_a_pointer_left_of_the_tape() {
+        let snapshot = TapeSnapshot::new(0, vec![7; 4], -1);
+        let hex = dump(&snapshot, DumpFormat::Hex).unwrap();
+        assert!(hex.contains("     -16:"));
+        assert!(hex.contains("[00]\n       0: 07 "));
+    }
+
+    #[test]
+    fn binary_and_json() {
+        let snapshot = TapeSnapshot::new(-1, vec![1, 2], 0);
+        assert_eq!(dump(&snapshot, DumpFormat::Binary).unwrap(), "\u{1}\u{2}");
+        assert_eq!(
+            dump(&snapshot, DumpFormat::Json).unwrap(),
+            "{\"first\":-1,\"pointer\":0,\"cells\":[1,2]}\n"
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let snapshot = TapeSnapshot {
+            segments: vec![
+                Segment {
+                    first: -2,
+                    cells: vec![1],
+                },
+                Segment {
+                    first: 100,
+                    cells: vec![2, 3],
+                },
+            ],
+            pointer: 101,
+        };
+        assert_eq!(snapshot.get(-2), 1);
+        assert_eq!(snapshot.get(-1), 0);
+        assert_eq!(snapshot.get(50), 0);
+        assert_eq!(snapshot.get(101), 3);
+        assert_eq!(snapshot.get(102), 0);
+        assert_eq!(
+            dump(&snapshot, DumpFormat::Json).unwrap(),
+            "{\"pointer\":101,\"segments\":[{\"first\":-2,\"cells\":[1]},{\"first\":100,\"cells\":[2,3]}]}\n"
+        );
+        assert!(dump(&snapshot, DumpFormat::Binary).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::ast::Ast;
+use crate::transpile::{lower, Instr};
+
+const INDENT: &str = "    ";
+
+fn is_command(c: u8) -> bool {
+    matches!(c, b'+' | b'-' | b'<' | b'>' | b'.' | b',' | b'[' | b']')
+}
+
+/// Lays source out with every `[` and `]` on its own line and loop bodies indented, runs of
+/// other commands wrapped after width commands. Comments are kept on their own lines with
+/// surrounding whitespace trimmed, so formatting formatted source changes nothing.
+/// The brackets of source must be balanced
+pub fn format(source: &[u8], width: usize) -> Vec<u8> {
+    let mut formatter = Formatter {
+        out: vec![],
+        line: vec![],
+        depth: 0,
+        width: width.max(1),
+    };
+
+    let mut rest = source;
+    while let Some(&c) = rest.first() {
+        if !is_command(c) {
+            let len = rest
+                .iter()
+                .position(|c| is_command(*c))
+                .unwrap_or(rest.len());
+            formatter.comment(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+
+        match c {
+            b'[' => {
+                formatter.flush();
+                formatter.push_line(b"[");
+                formatter.depth += 1;
+            }
+            b']' => {
+                formatter.flush();
+                formatter.depth -= 1;
+                formatter.push_line(b"]");
+            }
+            _ => {
+                formatter.line.push(c);
+                if formatter.line.len() == formatter.width {
+                    formatter.flush();
+                }
+            }
+        }
+        rest = &rest[1..];
+    }
+    formatter.flush();
+    formatter.out
+}
+
+struct Formatter {
+    out: Vec<u8>,
+    // Commands of the line being filled, without indentation
+    line: Vec<u8>,
+    depth: usize,
+    width: usize,
+}
+
+impl Formatter {
+    fn comment(&mut self, text: &[u8]) {
+        for line in text.split(|c| *c == b'\n') {
+            let line = line.trim_ascii();
+            if !line.is_empty() {
+                self.flush();
+                self.push_line(line);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.push_line(&line);
+        }
+    }
+
+    fn push_line(&mut self, line: &[u8]) {
+        for _ in 0..self.depth {
+            self.out.extend_from_slice(INDENT.as_bytes());
+        }
+        self.out.extend_from_slice(line);
+        self.out.push(b'\n');
+    }
+}
+
+/// The program's commands on a single line without comments, runs such as +- and <>
+/// that cancel out are dropped
+pub fn minify(ast: &Ast) -> String {
+    let mut out = String::new();
+    minify_into(&mut out, &lower(ast));
+    out.push('\n');
+    out
+}
+
+fn minify_into(out: &mut String, instrs: &[Instr]) {
+    for instr in instrs {
+        match instr {
+            Instr::Add(n) => {
+                let c = if *n > 0 { '+' } else { '-' };
+                out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+            }
+            Instr::Move(n) => {
+                let c = if *n > 0 { '>' } else { '<' };
+                out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+            }
+            Instr::Out => out.push('.'),
+            Instr::In => out.push(','),
+            Instr::Loop(body) => {
+                out.push('[');
+                minify_into(out, body);
+                out.push(']');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BrainFuckParser, Rule};
+    use pest::Parser;
+
+    const SOURCE: &[u8] = include_bytes!("../exmaples/helloworld.bf");
+
+    #[test]
+    fn layout() {
+        let formatted = format(b" add two ++[->+<] then print it >.", 2);
+        assert_eq!(
+            String::from_utf8(formatted).unwrap(),
+            "add two\n++\n[\n    ->\n    +<\n]\nthen print it\n>.\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for width in [1, 3, 80] {
+            let formatted = format(SOURCE, width);
+            assert_eq!(format(&formatted, width), formatted, "width {}", width);
+        }
+    }
+
+    #[test]
+    fn formatting_keeps_the_program() {
+        let ast = Ast::parse(SOURCE).unwrap();
+        let formatted = Ast::parse(&format(SOURCE, 7)).unwrap();
+        assert_eq!(minify(&formatted), minify(&ast));
+    }
+
+    #[test]
+    fn minify_keeps_input() {
+        let source = ",[.,] read, echo";
+        let pairs = BrainFuckParser::parse(Rule::Program, source).unwrap();
+        assert_eq!(minify(&Ast::from(pairs)), ",[.,],\n");
+        assert_eq!(minify(&Ast::parse(source.as_bytes()).unwrap()), ",[.,],\n");
+    }
+
+    #[test]
+    fn minify_folds_and_drops_comments() {
+        let ast = Ast::parse(b"+-+ a >< [ b .<>, ] c --").unwrap();
+        assert_eq!(minify(&ast), "+[.,]--\n");
+    }
+}
@@ -1,3 +1,41 @@
+//! Brainfuck interpreters, JIT compilers and transpilers.
+//!
+//! [`Program`] is the entry point: it parses a program and runs it with the interpreter in
+//! [`machine`], the x86-64 JIT in `native` or the LLVM backend in `codegen`, or translates
+//! it with [`transpile`]. Failures are returned as [`anyhow::Error`]s, including the
+//! pointer leaving the tape. The LLVM backend needs the `llvm` feature, which is on by default.
+
+/// Parsing source into an [`ast::Ast`] of operators
 pub mod ast;
+/// LLVM code generation, the JIT and ahead of time compilation
+#[cfg(feature = "llvm")]
+pub mod codegen;
+/// Final tape states and the formats they are written in
+pub mod dump;
+/// Source formatting and minification
+pub mod format;
+/// The interpreter
 pub mod machine;
+/// x86-64 JIT that does not need LLVM
+#[cfg(all(target_arch = "x86_64", unix))]
+pub mod native;
+/// Instruction and loop counts collected by the interpreter
+pub mod profiler;
+/// The [`Program`] facade over every backend
+pub mod program;
+#[cfg(feature = "llvm")]
+mod runtime;
+/// Static statistics about a program
+pub mod stats;
+/// Tape implementations the interpreter runs on
 pub mod tape;
+/// Step by step execution traces
+pub mod trace;
+/// Translation to C, Rust and WebAssembly
+pub mod transpile;
+
+#[cfg(feature = "llvm")]
+pub use codegen::{Emit, JitProgram};
+#[cfg(feature = "llvm")]
+pub use program::CompileOptions;
+pub use program::{OptLevel, Program};
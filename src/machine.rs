@@ -1,35 +1,175 @@
 use crate::ast::{Ast, Operator};
-use crate::tape::Tape;
+use crate::dump::TapeSnapshot;
+use crate::profiler::Profiler;
+use crate::tape::{FixedTape, Tape, TapeConfig};
+use crate::trace::Tracer;
+use crate::transpile::Eof;
+use anyhow::{anyhow, Context};
 use std::io::{self, Read, Write};
 
+/// Interpreter running an Ast on a tape of type T
 #[derive(Default)]
-pub struct Machine {
-    pc: usize,
-    tape: Tape,
+pub struct Machine<T: Tape = FixedTape> {
+    pc: isize,
+    tape: T,
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer>,
+    eof: Eof,
 }
 
-impl Machine {
+impl<T: Tape> Machine<T> {
+    pub fn new(tape: T) -> Self {
+        Self {
+            pc: 0,
+            tape,
+            profiler: None,
+            tracer: None,
+            eof: Eof::default(),
+        }
+    }
+
+    /// Moves the pointer to the start cell and copies the initial contents onto the tape,
+    /// the tape itself should already be config.len cells long. Fails when the tape cannot
+    /// hold the contents
+    pub fn with_config(mut self, config: &TapeConfig) -> anyhow::Result<Self> {
+        for (i, value) in config.contents.iter().enumerate() {
+            *self
+                .tape
+                .get_mut(i as isize)
+                .ok_or_else(|| anyhow!("initial contents do not fit the tape"))? = *value;
+        }
+        self.pc = config.start as isize;
+        Ok(self)
+    }
+
+    pub fn tape(&self) -> &T {
+        &self.tape
+    }
+
+    /// Index of the current cell
+    pub fn pointer(&self) -> isize {
+        self.pc
+    }
+
+    /// Cells the tape has backed and the pointer
+    pub fn snapshot(&self) -> TapeSnapshot {
+        TapeSnapshot::from_tape(self.tape(), self.pointer())
+    }
+
+    /// What , stores once input runs out, -1 like the other backends by default
+    pub fn with_eof(mut self, eof: Eof) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Counts every instruction and loop iteration for Profiler::report
+    pub fn with_profiler(mut self) -> Self {
+        self.profiler = Some(Profiler::default());
+        self
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Records every step with tracer
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Hands the tracer back so it can be finished once the program is done
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Runs program on stdin and stdout, panicking when it fails. Use run_with_io to
+    /// get the error instead
     pub fn run(&mut self, program: &Ast) {
-        for op in program.inner() {
+        if let Err(e) = self.run_with_io(program, &mut io::stdin(), &mut io::stdout()) {
+            panic!("{:#}", e);
+        }
+    }
+
+    /// Runs with , reading from input and . writing to output, returning an error
+    /// instead of panicking when a cell outside of the tape is accessed or the streams fail
+    pub fn run_with_io(
+        &mut self,
+        program: &Ast,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        for (offset, op) in program.iter() {
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.record(offset);
+            }
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer
+                    .record(offset, op, self.pc, self.tape.get(self.pc).copied())
+                    .context("error writing trace")?;
+            }
+
             match op {
-                Operator::IncPtr => self.pc += 1,
-                Operator::DecPtr => self.pc -= 1,
-                Operator::Inc => *self.tape.get_mut(self.pc).unwrap() += 1,
-                Operator::Dec => *self.tape.get_mut(self.pc).unwrap() -= 1,
-                Operator::Out => io::stdout()
-                    .write_all(&[*self.tape.get(self.pc).unwrap()])
-                    .unwrap(),
+                Operator::IncPtr => self.pc = self.tape.wrap(self.pc + 1),
+                Operator::DecPtr => self.pc = self.tape.wrap(self.pc - 1),
+                Operator::Inc => {
+                    let cell = self.cell_mut()?;
+                    *cell = cell.wrapping_add(1);
+                }
+                Operator::Dec => {
+                    let cell = self.cell_mut()?;
+                    *cell = cell.wrapping_sub(1);
+                }
+                Operator::Out => output
+                    .write_all(&[*Self::cell(&self.tape, self.pc)?])
+                    .context("error writing output")?,
                 Operator::In => {
-                    let mut buf = [0_u8, 1];
-                    io::stdin().read_exact(&mut buf).unwrap();
-                    *self.tape.get_mut(self.pc).unwrap() = buf[0];
+                    let eof = self.eof;
+                    let cell = self.cell_mut()?;
+                    match read_byte(input).context("error reading input")? {
+                        Some(byte) => *cell = byte,
+                        None => match eof {
+                            Eof::MinusOne => *cell = u8::MAX,
+                            Eof::Zero => *cell = 0,
+                            Eof::Unchanged => {}
+                        },
+                    }
                 }
                 Operator::Loop(program) => {
-                    while *self.tape.get(self.pc).unwrap() != 0 {
-                        self.run(program);
+                    while *Self::cell(&self.tape, self.pc)? != 0 {
+                        if let Some(profiler) = self.profiler.as_mut() {
+                            profiler.record_iteration(offset);
+                        }
+                        self.run_with_io(program, input, output)?;
                     }
                 }
             }
         }
+        Ok(())
+    }
+
+    fn cell(tape: &T, pc: isize) -> anyhow::Result<&u8> {
+        tape.get(pc)
+            .ok_or_else(|| anyhow!("pointer {} is out of the tape bounds", pc))
+    }
+
+    fn cell_mut(&mut self) -> anyhow::Result<&mut u8> {
+        let pc = self.pc;
+        self.tape
+            .get_mut(pc)
+            .ok_or_else(|| anyhow!("pointer {} is out of the tape bounds", pc))
+    }
+}
+
+// One byte of input, None at the end of it
+fn read_byte(input: &mut dyn Read) -> io::Result<Option<u8>> {
+    let mut buf = [0_u8; 1];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
 }
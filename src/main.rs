@@ -1,19 +1,27 @@
-use clap::Parser;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
 use pest::Parser as PestParser;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-#[allow(unused_imports)]
-use ast::{Ast, Tokenizer};
-use codegen::IRCodegen;
-#[allow(unused_imports)]
-use machine::Machine;
-
-mod ast;
-mod codegen;
-mod machine;
-mod tape;
+use brainfuck_rs::ast::Ast;
+#[cfg(feature = "llvm")]
+use brainfuck_rs::codegen::{validate_identifier, Emit, IRCodegen};
+use brainfuck_rs::dump::{DumpFormat, TapeSnapshot};
+use brainfuck_rs::machine::Machine;
+#[cfg(all(target_arch = "x86_64", unix))]
+use brainfuck_rs::native;
+#[cfg(unix)]
+use brainfuck_rs::tape::MmapTape;
+use brainfuck_rs::tape::{CircularTape, FixedTape, GrowableTape, SparseTape, Tape, TapeConfig};
+use brainfuck_rs::trace::{TraceFormat, Tracer};
+use brainfuck_rs::transpile::{self, CellType, Eof, Language, TranspileOptions};
+use brainfuck_rs::{ast, format, stats, tape};
+#[cfg(feature = "llvm")]
+use inkwell::OptimizationLevel;
 
 #[derive(clap::ValueEnum, Clone)]
 enum ParserMode {
@@ -23,57 +31,932 @@ enum ParserMode {
 
 #[derive(clap::ValueEnum, Clone)]
 enum Mode {
-    Jit,     // Just in time Compilation using LLVM
-    Compile, // Complies using llvm and clang
-    Machine, // Custom interpreter much slower
+    /// Just in time Compilation using LLVM
+    Jit,
+    /// Built-in x86-64 jit without LLVM, other architectures use the interpreter
+    Native,
+    /// Custom interpreter much slower
+    Machine,
+}
+
+#[cfg(feature = "llvm")]
+const DEFAULT_MODE: Mode = Mode::Jit;
+#[cfg(not(feature = "llvm"))]
+const DEFAULT_MODE: Mode = Mode::Native;
+
+#[derive(clap::ValueEnum, Clone)]
+enum LanguageArg {
+    C,
+    Rust,
+    /// WebAssembly text format
+    Wat,
+    /// WebAssembly binary module
+    Wasm,
+}
+
+impl From<LanguageArg> for Language {
+    fn from(l: LanguageArg) -> Self {
+        match l {
+            LanguageArg::C => Language::C,
+            LanguageArg::Rust => Language::Rust,
+            LanguageArg::Wat => Language::Wat,
+            LanguageArg::Wasm => Language::Wasm,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum EofArg {
+    /// Stores 255, what storing getchar's EOF does
+    MinusOne,
+    Zero,
+    /// Leaves the cell as it was
+    Unchanged,
+}
+
+impl From<EofArg> for Eof {
+    fn from(e: EofArg) -> Self {
+        match e {
+            EofArg::MinusOne => Eof::MinusOne,
+            EofArg::Zero => Eof::Zero,
+            EofArg::Unchanged => Eof::Unchanged,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum CellTypeArg {
+    U8,
+    U16,
+    U32,
+}
+
+impl From<CellTypeArg> for CellType {
+    fn from(c: CellTypeArg) -> Self {
+        match c {
+            CellTypeArg::U8 => CellType::U8,
+            CellTypeArg::U16 => CellType::U16,
+            CellTypeArg::U32 => CellType::U32,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum TapeMode {
+    /// --tape-len cells
+    Fixed,
+    /// Starts with --tape-len cells and grows on demand in both directions up to --memory-cap cells
+    Growable,
+    /// Only allocates touched pages up to --memory-cap cells, for programs that jump far
+    Sparse,
+    /// --tape-len cells reserved with mmap and paged in on first use
+    Mmap,
+    /// --tape-len cells where the pointer wraps from the last cell to the first and back
+    Circular,
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum DumpFormatArg {
+    Hex,
+    Binary,
+    Json,
+}
+
+impl From<DumpFormatArg> for DumpFormat {
+    fn from(f: DumpFormatArg) -> Self {
+        match f {
+            DumpFormatArg::Hex => DumpFormat::Hex,
+            DumpFormatArg::Binary => DumpFormat::Binary,
+            DumpFormatArg::Json => DumpFormat::Json,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum ProfileFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum TraceFormatArg {
+    Text,
+    Binary,
+}
+
+impl From<TraceFormatArg> for TraceFormat {
+    fn from(f: TraceFormatArg) -> Self {
+        match f {
+            TraceFormatArg::Text => TraceFormat::Text,
+            TraceFormatArg::Binary => TraceFormat::Binary,
+        }
+    }
 }
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    // Brainfuck source code file path
-    #[arg(short, long)]
-    filepath: String,
+    #[command(subcommand)]
+    command: Command,
+}
 
-    // Switches between pest and internal parser
+// Every subcommand exits with 0 on success, 1 when it fails, 2 on invalid arguments and
+// 3 when fmt --check or check find a problem with the program
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Runs a program with the LLVM jit, the native jit or the interpreter")]
+    Run(RunArgs),
+    #[command(about = "Compiles a program ahead of time or translates it to another language")]
+    Build(BuildArgs),
+    #[command(about = "Indents loops and wraps long runs of commands")]
+    Fmt(FmtArgs),
+    #[command(about = "Reports unbalanced brackets")]
+    Check(CheckArgs),
+    #[command(about = "Strips comments and runs of commands that cancel out")]
+    Minify(MinifyArgs),
+    #[command(about = "Counts commands, loops and nesting depth")]
+    Stats(StatsArgs),
+    #[command(about = "Runs a program in the interpreter with profiling, tracing and tape dumps")]
+    Debug(DebugArgs),
+}
+
+#[derive(Args)]
+struct SourceArgs {
+    /// Brainfuck source code file path
+    file: PathBuf,
+
+    /// Switches between pest and internal parser
     #[arg(short, long, value_enum, default_value_t = ParserMode::Pest)]
     parsemode: ParserMode,
+}
+
+// A program file along with its parsed Ast
+struct Source {
+    #[cfg(feature = "llvm")]
+    path: PathBuf,
+    bytes: Vec<u8>,
+    ast: Ast,
+}
 
-    #[arg(short, long, value_enum, default_value_t = Mode::Jit)]
+impl SourceArgs {
+    fn load(&self) -> anyhow::Result<Source> {
+        let bytes = read_program(&self.file)?;
+        let ast = match self.parsemode {
+            ParserMode::Pest => {
+                let source = std::str::from_utf8(&bytes).context("program is not valid UTF-8")?;
+                let pairs = ast::BrainFuckParser::parse(ast::Rule::Program, source)
+                    .context("error tokenizing program")?;
+                Ast::from(pairs)
+            }
+            ParserMode::Internal => Ast::parse(&bytes)?,
+        };
+
+        Ok(Source {
+            #[cfg(feature = "llvm")]
+            path: self.file.clone(),
+            bytes,
+            ast,
+        })
+    }
+}
+
+#[derive(Args)]
+struct TapeArgs {
+    /// How the tape stores its cells
+    #[arg(long, value_enum, default_value_t = TapeMode::Fixed)]
+    tape: TapeMode,
+
+    /// Number of cells on the tape, shared by every mode
+    #[arg(long, default_value_t = tape::DEFAULT_LEN)]
+    tape_len: usize,
+
+    /// Cell the pointer starts at, counted from the leftmost cell
+    #[arg(long, default_value_t = 0)]
+    tape_start: usize,
+
+    /// Initial cell values from the leftmost cell, written as comma separated bytes
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["tape_load", "tape_hex"])]
+    tape_init: Vec<u8>,
+
+    /// Loads the initial cell values from the leftmost cell out of a file
+    #[arg(long, conflicts_with = "tape_hex")]
+    tape_load: Option<PathBuf>,
+
+    /// Initial cell values from the leftmost cell written as a hex string
+    #[arg(long)]
+    tape_hex: Option<String>,
+
+    /// Maximum number of cells a growable or sparse tape may use
+    #[arg(long, default_value_t = tape::DEFAULT_MEMORY_CAP)]
+    memory_cap: usize,
+
+    /// Backs an mmap tape with this file instead of anonymous memory
+    #[arg(long)]
+    tape_file: Option<PathBuf>,
+}
+
+impl TapeArgs {
+    fn config(&self) -> anyhow::Result<TapeConfig> {
+        let contents = if let Some(path) = &self.tape_load {
+            std::fs::read(path)
+                .with_context(|| format!("error reading tape contents from {}", path.display()))?
+        } else if let Some(hex) = &self.tape_hex {
+            tape::parse_hex(hex).map_err(|e| anyhow::anyhow!("invalid --tape-hex: {}", e))?
+        } else {
+            self.tape_init.clone()
+        };
+        let config = TapeConfig {
+            len: self.tape_len,
+            start: self.tape_start,
+            contents,
+        };
+        if let Err(e) = config.validate() {
+            bail!("invalid tape configuration: {}", e);
+        }
+        if matches!(self.tape, TapeMode::Growable) && config.len > self.memory_cap {
+            bail!("--tape-len is larger than --memory-cap");
+        }
+        if matches!(self.tape, TapeMode::Sparse) && config.contents.len() > self.memory_cap {
+            bail!("the initial tape contents are larger than --memory-cap");
+        }
+        Ok(config)
+    }
+
+    // The interpreter's tape, every tape mode is supported
+    fn open(&self, config: &TapeConfig) -> anyhow::Result<Box<dyn Tape>> {
+        Ok(match self.tape {
+            TapeMode::Fixed => Box::new(FixedTape::new(config.len)),
+            TapeMode::Growable => Box::new(GrowableTape::new(config.len, self.memory_cap)),
+            TapeMode::Sparse => Box::new(SparseTape::new(self.memory_cap)),
+            TapeMode::Circular => Box::new(CircularTape::new(config.len)),
+            #[cfg(unix)]
+            TapeMode::Mmap => Box::new(
+                match &self.tape_file {
+                    Some(path) => MmapTape::file(path, config.len),
+                    None => MmapTape::anonymous(config.len),
+                }
+                .context("unable to map tape memory")?,
+            ),
+            #[cfg(not(unix))]
+            TapeMode::Mmap => bail!("mmap tapes are only supported on unix"),
+        })
+    }
+}
+
+#[derive(Args)]
+struct DumpArgs {
+    /// Writes the final tape to this file, - writes it to stderr
+    #[arg(long)]
+    tape_dump: Option<String>,
+
+    /// Format of --tape-dump
+    #[arg(long, value_enum, default_value_t = DumpFormatArg::Hex)]
+    tape_dump_format: DumpFormatArg,
+}
+
+impl DumpArgs {
+    fn write(&self, snapshot: &TapeSnapshot) -> anyhow::Result<()> {
+        let format = self.tape_dump_format.clone().into();
+        match self.tape_dump.as_deref() {
+            Some("-") => snapshot.write(format, &mut io::stderr()),
+            Some(path) => snapshot.write(
+                format,
+                &mut File::create(path)
+                    .with_context(|| format!("unable to create tape dump file {}", path))?,
+            ),
+            None => Ok(()),
+        }
+        .context("error writing tape dump")
+    }
+}
+
+// Options shared by the jit and compiled code
+#[cfg(feature = "llvm")]
+#[derive(Args)]
+struct CodegenArgs {
+    /// LLVM optimization level of the jit and compiled code, 0 to 3
+    #[arg(short = 'O', long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: u8,
+
+    /// Emits DWARF debug info mapping jit and compiled code to lines and columns of the program
+    #[arg(short = 'g', long)]
+    debug_info: bool,
+
+    /// Checks the pointer before every cell access and reports the source offset of the
+    /// instruction that touched a cell outside of a fixed tape
+    #[arg(long)]
+    checked: bool,
+
+    /// Maps the tape between guard pages and reports the cell a fault happened at,
+    /// cheaper than --checked. Buffered output is lost on a fault unless --unbuffered is set
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "checked")]
+    guarded: bool,
+
+    /// Writes output byte by byte instead of buffering it until input is read
+    #[arg(long)]
+    unbuffered: bool,
+}
+
+#[cfg(feature = "llvm")]
+impl CodegenArgs {
+    fn codegen<'a>(
+        &self,
+        source: &'a Source,
+        tape: &TapeArgs,
+        config: TapeConfig,
+    ) -> anyhow::Result<IRCodegen<'a, 'a>> {
+        // Growable and circular tapes never leave their memory and guard pages
+        // already catch what the checks would
+        if self.checked && !matches!(tape.tape, TapeMode::Fixed) {
+            bail!("--checked only applies to fixed tapes");
+        }
+        #[cfg(unix)]
+        if self.guarded && !matches!(tape.tape, TapeMode::Fixed) {
+            bail!("--guarded only applies to fixed tapes");
+        }
+        #[cfg(unix)]
+        if self.checked && self.guarded {
+            bail!("--checked and --guarded cannot be combined");
+        }
+        let codegen = match tape.tape {
+            TapeMode::Fixed => IRCodegen::from(&source.ast).tape_config(config),
+            TapeMode::Growable => IRCodegen::from(&source.ast)
+                .tape_config(config)
+                .growable_tape(tape.memory_cap as u64),
+            TapeMode::Circular => IRCodegen::from(&source.ast)
+                .tape_config(config)
+                .circular_tape(),
+            TapeMode::Sparse | TapeMode::Mmap => {
+                bail!("sparse and mmap tapes are only supported by the interpreter")
+            }
+        };
+        let codegen = codegen.optimize(optimization_level(self.opt_level));
+        let codegen = match self.debug_info {
+            true => codegen.debug_info(&source.path, &source.bytes),
+            false => codegen,
+        };
+        let codegen = match self.unbuffered {
+            true => codegen.unbuffered(),
+            false => codegen,
+        };
+        let codegen = match self.checked {
+            true => codegen.checked(),
+            false => codegen,
+        };
+        #[cfg(unix)]
+        let codegen = match self.guarded {
+            true => codegen.guarded(),
+            false => codegen,
+        };
+        Ok(codegen)
+    }
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// How the program is executed
+    #[arg(short, long, value_enum, default_value_t = DEFAULT_MODE)]
     mode: Mode,
+
+    #[command(flatten)]
+    tape: TapeArgs,
+
+    /// What , stores at the end of input
+    #[arg(long, value_enum, default_value_t = EofArg::MinusOne)]
+    eof: EofArg,
+
+    #[command(flatten)]
+    dump: DumpArgs,
+
+    #[cfg(feature = "llvm")]
+    #[command(flatten)]
+    codegen: CodegenArgs,
+
+    /// Level the jit generates machine code with, defaults to --opt-level
+    #[cfg(feature = "llvm")]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=3))]
+    jit_opt_level: Option<u8>,
 }
 
-#[allow(dead_code)]
-fn read_program(filepath: &Path) -> anyhow::Result<Vec<u8>> {
-    let mut f = BufReader::new(File::open(filepath).expect("Unable to open program file"));
+impl RunArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let source = self.source.load()?;
+        let config = self.tape.config()?;
+
+        match self.mode {
+            #[cfg(feature = "llvm")]
+            Mode::Jit => {
+                let level = self.jit_opt_level.unwrap_or(self.codegen.opt_level);
+                let codegen = self
+                    .codegen
+                    .codegen(&source, &self.tape, config)?
+                    .eof(self.eof.clone().into())
+                    .jit_opt_level(optimization_level(level));
+                let codegen = match self.dump.tape_dump {
+                    Some(_) => codegen.dump_tape(),
+                    None => codegen,
+                };
+                if let Some(snapshot) = codegen.jit()? {
+                    self.dump.write(&snapshot)?;
+                }
+            }
+            #[cfg(not(feature = "llvm"))]
+            Mode::Jit => {
+                bail!("the jit needs LLVM, rebuild with --features llvm or use --mode native")
+            }
+            Mode::Native => {
+                if !matches!(self.tape.tape, TapeMode::Fixed) {
+                    bail!("the native jit only supports fixed tapes");
+                }
+                #[cfg(all(target_arch = "x86_64", unix))]
+                let (tape, pointer) = {
+                    let mut tape = config.initial_cells();
+                    let pointer = native::NativeJit::new(&source.ast)
+                        .context("unable to map executable memory")?
+                        .eof(self.eof.clone().into())
+                        .run(
+                            &mut tape,
+                            config.start,
+                            &mut io::stdin().lock(),
+                            &mut io::stdout().lock(),
+                        )?;
+                    (tape, pointer)
+                };
+                #[cfg(not(all(target_arch = "x86_64", unix)))]
+                let (tape, pointer) = {
+                    let mut machine = Machine::new(FixedTape::new(config.len))
+                        .with_config(&config)?
+                        .with_eof(self.eof.clone().into());
+                    machine.run_with_io(&source.ast, &mut io::stdin(), &mut io::stdout())?;
+                    let tape = machine.snapshot().segments.swap_remove(0).cells;
+                    (tape, machine.pointer())
+                };
 
-    let mut bytes = vec![];
-    f.read_to_end(&mut bytes).expect("error reading all bytes");
-    Ok(bytes)
+                if self.dump.tape_dump.is_some() {
+                    self.dump.write(&TapeSnapshot::new(0, tape, pointer))?;
+                }
+            }
+            Mode::Machine => {
+                let mut machine = Machine::new(self.tape.open(&config)?)
+                    .with_config(&config)?
+                    .with_eof(self.eof.clone().into());
+                machine.run_with_io(&source.ast, &mut io::stdin(), &mut io::stdout())?;
+                if self.dump.tape_dump.is_some() {
+                    self.dump.write(&machine.snapshot())?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-fn main() {
-    let cli = Cli::parse();
-    let path = Path::new(&cli.filepath);
+#[derive(Args)]
+struct BuildArgs {
+    #[command(flatten)]
+    source: SourceArgs,
 
-    let ast = match cli.parsemode {
-        ParserMode::Pest => {
-            let source = std::fs::read_to_string(path).expect("error reading source code");
+    /// Executable or, when it ends in .o, object file. Defaults to the program file name
+    /// without its extension, translated programs are written to stdout unless it is given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Translates the program to this language without LLVM instead of compiling it
+    #[arg(long, value_enum)]
+    language: Option<LanguageArg>,
+
+    /// Width of a cell in translated programs, compiled code uses u8
+    #[arg(long, value_enum, default_value_t = CellTypeArg::U8, requires = "language")]
+    cell_type: CellTypeArg,
+
+    /// What , stores at the end of input
+    #[arg(long, value_enum, default_value_t = EofArg::MinusOne)]
+    eof: EofArg,
+
+    #[command(flatten)]
+    tape: TapeArgs,
+
+    /// Artifacts to write, written as kind or kind=path with - for stdout. Kinds are llvm-ir,
+    /// llvm-bc, asm, obj, exe, lib and header, paths default to --output with the kind's extension
+    #[cfg(feature = "llvm")]
+    #[arg(long, value_delimiter = ',', value_parser = parse_emit, conflicts_with = "language")]
+    emit: Vec<(Emit, Option<PathBuf>)>,
+
+    /// Compiles the program to a reentrant C function named bf_<LIB> instead of an executable,
+    /// writing a static library and a header unless --emit says otherwise
+    #[cfg(feature = "llvm")]
+    #[arg(long, value_parser = |s: &str| validate_identifier(s).map(|()| s.to_string()), conflicts_with = "language")]
+    lib: Option<String>,
+
+    /// Target triple to generate code for, such as aarch64-unknown-linux-gnu,
+    /// riscv64gc-unknown-linux-gnu or wasm32-unknown-unknown. Defaults to the host
+    #[cfg(feature = "llvm")]
+    #[arg(long, conflicts_with = "language")]
+    target: Option<String>,
+
+    /// Target cpu, defaults to the host cpu or generic when cross compiling
+    #[cfg(feature = "llvm")]
+    #[arg(long, conflicts_with = "language")]
+    cpu: Option<String>,
+
+    /// Target features such as +neon,-sve
+    #[cfg(feature = "llvm")]
+    #[arg(long, conflicts_with = "language")]
+    features: Option<String>,
+
+    /// Compiled programs write their final tape to the file named by BF_TAPE_DUMP,
+    /// in the format named by BF_TAPE_DUMP_FORMAT
+    #[cfg(feature = "llvm")]
+    #[arg(long, conflicts_with = "language")]
+    tape_dump: bool,
+
+    #[cfg(feature = "llvm")]
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+impl BuildArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let source = self.source.load()?;
+        let config = self.tape.config()?;
+
+        match &self.language {
+            Some(language) => self.translate(&source, config, language.clone().into()),
+            #[cfg(feature = "llvm")]
+            None => self.compile(&source, config),
+            #[cfg(not(feature = "llvm"))]
+            None => bail!("compiling needs LLVM, rebuild with --features llvm or pass --language"),
+        }
+    }
+
+    fn translate(
+        &self,
+        source: &Source,
+        config: TapeConfig,
+        language: Language,
+    ) -> anyhow::Result<()> {
+        if !matches!(self.tape.tape, TapeMode::Fixed | TapeMode::Circular) {
+            bail!("translated programs only support fixed and circular tapes");
+        }
+        let options = TranspileOptions {
+            tape: config,
+            cell: self.cell_type.clone().into(),
+            eof: self.eof.clone().into(),
+            circular: matches!(self.tape.tape, TapeMode::Circular),
+        };
+        let output = transpile::transpile(&source.ast, language, &options);
+        write_output(self.output.as_deref(), &output)
+    }
+
+    #[cfg(feature = "llvm")]
+    fn compile(&self, source: &Source, config: TapeConfig) -> anyhow::Result<()> {
+        let codegen = self
+            .codegen
+            .codegen(source, &self.tape, config)?
+            .eof(self.eof.clone().into());
+        let codegen = match &self.target {
+            Some(triple) => codegen.target(triple),
+            None => codegen,
+        };
+        let codegen = match &self.cpu {
+            Some(cpu) => codegen.cpu(cpu),
+            None => codegen,
+        };
+        let codegen = match &self.features {
+            Some(features) => codegen.features(features),
+            None => codegen,
+        };
+        let codegen = match &self.lib {
+            Some(name) => codegen.library(name),
+            None => codegen,
+        };
+        let codegen = match self.tape_dump {
+            true => codegen.dump_tape(),
+            false => codegen,
+        };
+
+        let path = &source.path;
+        let output = self
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(path.file_stem().unwrap_or(path.as_os_str())));
+        match (self.emit.is_empty(), &self.lib) {
+            (true, None) => codegen.compile(&output),
+            (true, Some(_)) => codegen.emit(&[
+                (Emit::Lib, Some(output.with_extension("a"))),
+                (Emit::Header, Some(output.with_extension("h"))),
+            ]),
+            (false, _) => {
+                let artifacts = self
+                    .emit
+                    .iter()
+                    .map(|(kind, path)| match path {
+                        Some(path) if path.as_os_str() == "-" => (*kind, None),
+                        Some(path) => (*kind, Some(path.clone())),
+                        None => match kind.extension() {
+                            Some(ext) => (*kind, Some(output.with_extension(ext))),
+                            None => (*kind, Some(output.clone())),
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                codegen.emit(&artifacts)
+            }
+        }
+    }
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    /// Brainfuck source code file path
+    file: PathBuf,
+
+    /// Rewrites the file in place instead of printing the formatted program
+    #[arg(short, long, conflicts_with = "check")]
+    write: bool,
+
+    /// Fails when the file is not formatted instead of printing it
+    #[arg(long)]
+    check: bool,
+
+    /// Number of commands on a line before it is wrapped
+    #[arg(long, default_value_t = 80)]
+    width: usize,
+}
+
+impl FmtArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let bytes = read_program(&self.file)?;
+        Ast::parse(&bytes).with_context(|| format!("unable to format {}", self.file.display()))?;
+
+        let formatted = format::format(&bytes, self.width);
+        if self.check {
+            if formatted != bytes {
+                return Err(
+                    CheckFailed(format!("{} is not formatted", self.file.display())).into(),
+                );
+            }
+        } else if self.write {
+            if formatted != bytes {
+                std::fs::write(&self.file, formatted)
+                    .with_context(|| format!("unable to write {}", self.file.display()))?;
+            }
+        } else {
+            write_output(None, &formatted)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Brainfuck source code files to check
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+impl CheckArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let mut failed = 0;
+        for file in &self.files {
+            if let Err(e) = read_program(file).and_then(|bytes| Ast::parse(&bytes)) {
+                eprintln!("{}: {:#}", file.display(), e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            let message = format!("{} of {} files have errors", failed, self.files.len());
+            return Err(CheckFailed(message).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct MinifyArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Writes the minified program to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl MinifyArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let source = self.source.load()?;
+        let minified = format::minify(&source.ast);
+        write_output(self.output.as_deref(), minified.as_bytes())
+    }
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Prints the statistics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+impl StatsArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let source = self.source.load()?;
+        let stats = stats::Stats::new(&source.bytes, &source.ast);
+        match self.json {
+            true => println!("{}", stats.to_json()),
+            false => print!("{}", stats),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    tape: TapeArgs,
+
+    /// What , stores at the end of input
+    #[arg(long, value_enum, default_value_t = EofArg::MinusOne)]
+    eof: EofArg,
+
+    #[command(flatten)]
+    dump: DumpArgs,
+
+    /// Counts executed instructions and loop iterations
+    #[arg(long)]
+    profile: bool,
+
+    /// Format of the profile report
+    #[arg(long, value_enum, default_value_t = ProfileFormat::Text)]
+    profile_format: ProfileFormat,
+
+    /// Number of hot loops and instructions in the profile report, 0 shows all of them
+    #[arg(long, default_value_t = 10)]
+    profile_limit: usize,
+
+    /// Logs every executed instruction to this file
+    #[arg(long)]
+    trace: Option<PathBuf>,
 
-            let pairs = ast::BrainFuckParser::parse(ast::Rule::Program, &source)
-                .expect("error tokenizing program");
-            Ast::from(pairs)
+    /// Format of the trace log
+    #[arg(long, value_enum, default_value_t = TraceFormatArg::Text)]
+    trace_format: TraceFormatArg,
+
+    /// Only trace instructions between these source offsets, written as start..end
+    #[arg(long, value_parser = parse_range)]
+    trace_range: Option<Range<usize>>,
+
+    /// Only trace every nth step
+    #[arg(long, default_value_t = 1)]
+    trace_every: u64,
+}
+
+impl DebugArgs {
+    fn execute(&self) -> anyhow::Result<()> {
+        let source = self.source.load()?;
+        let config = self.tape.config()?;
+
+        let mut machine = Machine::new(self.tape.open(&config)?)
+            .with_config(&config)?
+            .with_eof(self.eof.clone().into());
+        if self.profile {
+            machine = machine.with_profiler();
         }
-        ParserMode::Internal => {
-            let bytes = read_program(path).expect("error reading source code file");
-            let tokenizer = Tokenizer::from(bytes);
-            Ast::from(tokenizer)
+        if let Some(trace) = &self.trace {
+            let mut tracer = Tracer::create(trace, self.trace_format.clone().into())
+                .with_context(|| format!("unable to create trace file {}", trace.display()))?
+                .every(self.trace_every);
+            if let Some(range) = self.trace_range.clone() {
+                tracer = tracer.range(range);
+            }
+            machine = machine.with_tracer(tracer);
         }
+
+        // The tape, trace and profile are written even when the program fails,
+        // they are what explains the failure
+        let result = machine.run_with_io(&source.ast, &mut io::stdin(), &mut io::stdout());
+
+        if self.dump.tape_dump.is_some() {
+            self.dump.write(&machine.snapshot())?;
+        }
+
+        if let Some(tracer) = machine.take_tracer() {
+            tracer.finish().context("error writing trace")?;
+        }
+
+        if let Some(profiler) = machine.profiler() {
+            let report = profiler.report(&source.bytes).limit(self.profile_limit);
+            match self.profile_format {
+                ProfileFormat::Text => eprint!("{}", report),
+                ProfileFormat::Json => eprintln!("{}", report.to_json()),
+            }
+        }
+
+        result
+    }
+}
+
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("expected start..end, got {}", s))?;
+    let start = start.parse::<usize>().map_err(|e| e.to_string())?;
+    let end = end.parse::<usize>().map_err(|e| e.to_string())?;
+    Ok(start..end)
+}
+
+#[cfg(feature = "llvm")]
+fn parse_emit(s: &str) -> Result<(Emit, Option<PathBuf>), String> {
+    let (name, path) = match s.split_once('=') {
+        Some((name, path)) => (name, Some(PathBuf::from(path))),
+        None => (s, None),
     };
+    let kind = [
+        Emit::LlvmIr,
+        Emit::LlvmBc,
+        Emit::Asm,
+        Emit::Obj,
+        Emit::Exe,
+        Emit::Lib,
+        Emit::Header,
+    ]
+    .into_iter()
+    .find(|kind| kind.name() == name)
+    .ok_or_else(|| {
+        format!(
+            "unknown artifact {}, expected llvm-ir, llvm-bc, asm, obj, exe, lib or header",
+            name
+        )
+    })?;
+    Ok((kind, path))
+}
+
+#[cfg(feature = "llvm")]
+fn optimization_level(level: u8) -> OptimizationLevel {
+    match level {
+        0 => OptimizationLevel::None,
+        1 => OptimizationLevel::Less,
+        2 => OptimizationLevel::Default,
+        _ => OptimizationLevel::Aggressive,
+    }
+}
+
+// A program that fmt --check or check rejected, as opposed to a command that failed to run
+#[derive(Debug)]
+struct CheckFailed(String);
+
+impl std::fmt::Display for CheckFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-    match cli.mode {
-        Mode::Jit => IRCodegen::from(&ast).jit(),
-        Mode::Compile => IRCodegen::from(&ast).compile("brainfuck"),
-        Mode::Machine => Machine::default().run(&ast),
+impl std::error::Error for CheckFailed {}
+
+fn read_program(filepath: &Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(filepath).with_context(|| format!("unable to read {}", filepath.display()))
+}
+
+// Writes bytes to path, or to stdout when there is none
+fn write_output(path: Option<&Path>, bytes: &[u8]) -> anyhow::Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, bytes)
+            .with_context(|| format!("unable to write {}", path.display())),
+        None => io::stdout()
+            .write_all(bytes)
+            .context("unable to write to stdout"),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Run(args) => args.execute(),
+        Command::Build(args) => args.execute(),
+        Command::Fmt(args) => args.execute(),
+        Command::Check(args) => args.execute(),
+        Command::Minify(args) => args.execute(),
+        Command::Stats(args) => args.execute(),
+        Command::Debug(args) => args.execute(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            if e.is::<CheckFailed>() {
+                ExitCode::from(3)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
     }
 }
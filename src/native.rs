@@ -0,0 +1,353 @@
+use crate::ast::Ast;
+use crate::transpile::{lower, Eof, Instr};
+use anyhow::{bail, Context};
+use std::io::{self, Read, Write};
+use std::ptr;
+
+// fn(start, end, ptr, io) -> exit, where end is one past the last cell
+type EntryFn = unsafe extern "sysv64" fn(*mut u8, *mut u8, *mut u8, *mut NativeIo) -> Exit;
+
+// Returned in rax and rdx, failed is 1 when the program touched a cell outside of the tape
+#[repr(C)]
+struct Exit {
+    ptr: *mut u8,
+    failed: u64,
+}
+
+/// Program translated straight to x86-64 machine code, without going through LLVM.
+/// Cells are bytes on a fixed tape, , stores -1 at the end of input unless eof says otherwise
+pub struct NativeJit {
+    code: *mut u8,
+    len: usize,
+    eof: Eof,
+}
+
+impl NativeJit {
+    /// Assembles ast into executable pages, fails when they cannot be mapped
+    pub fn new(ast: &Ast) -> io::Result<Self> {
+        let mut asm = Assembler::default();
+        asm.prologue();
+        asm.block(&lower(ast));
+        asm.epilogue();
+        let bytes = asm.finish();
+
+        // Written while the pages are writable, then switched to executable
+        unsafe {
+            let len = bytes.len();
+            let code = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if code == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            ptr::copy_nonoverlapping(bytes.as_ptr(), code as *mut u8, len);
+            if libc::mprotect(code, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                let error = io::Error::last_os_error();
+                libc::munmap(code, len);
+                return Err(error);
+            }
+
+            Ok(Self {
+                code: code as *mut u8,
+                len,
+                eof: Eof::default(),
+            })
+        }
+    }
+
+    /// What , stores once input runs out
+    pub fn eof(mut self, eof: Eof) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Runs the program with the pointer on cell pointer of tape and returns where it ended up.
+    /// Like the Machine, the pointer may move off of the tape as long as no cell outside of it
+    /// is touched, doing so stops the program with an error
+    pub fn run(
+        &self,
+        tape: &mut [u8],
+        pointer: usize,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<isize> {
+        if pointer >= tape.len() {
+            bail!("pointer {} is out of the tape bounds", pointer);
+        }
+
+        let mut io = NativeIo {
+            input,
+            output,
+            eof: self.eof,
+            error: None,
+        };
+        let range = tape.as_mut_ptr_range();
+        let entry: EntryFn = unsafe { std::mem::transmute(self.code) };
+        let exit = unsafe { entry(range.start, range.end, range.start.add(pointer), &mut io) };
+
+        if let Some(error) = io.error {
+            return Err(error).context("error running native code");
+        }
+        io.output.flush().context("error writing output")?;
+        let pointer = (exit.ptr as isize).wrapping_sub(range.start as isize);
+        if exit.failed != 0 {
+            bail!("pointer {} is out of the tape bounds", pointer);
+        }
+        Ok(pointer)
+    }
+}
+
+impl Drop for NativeJit {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.code as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+// Streams of a run, the code keeps a pointer to it in r14 and passes it to the callbacks.
+// Unwinding out of the machine code is not possible, so the first error is kept here
+// and every read after it sees the end of input
+struct NativeIo<'a> {
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    eof: Eof,
+    error: Option<io::Error>,
+}
+
+extern "sysv64" fn native_putchar(io: *mut NativeIo, c: i32) {
+    let io = unsafe { &mut *io };
+    if io.error.is_none() {
+        io.error = io.output.write_all(&[c as u8]).err();
+    }
+}
+
+// Returns the new value of the current cell, which holds cell. Flushes pending output
+// so prompts show up before blocking on input
+extern "sysv64" fn native_getchar(io: *mut NativeIo, cell: u8) -> u8 {
+    let io = unsafe { &mut *io };
+    if io.error.is_none() {
+        io.error = io.output.flush().err();
+    }
+
+    let mut buf = [0_u8];
+    while io.error.is_none() {
+        match io.input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => return buf[0],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => io.error = Some(e),
+        }
+    }
+    match io.eof {
+        Eof::MinusOne => u8::MAX,
+        Eof::Zero => 0,
+        Eof::Unchanged => cell,
+    }
+}
+
+// Register use: rbx holds the first cell, r13 one past the last one, r12 the pointer and
+// r14 the NativeIo. All of them are callee saved, so they survive the calls into the callbacks
+struct Assembler {
+    code: Vec<u8>,
+    // rel32 operands of the jumps that leave the program once the pointer is out of bounds
+    exits: Vec<usize>,
+    // Whether r12 was bounds checked since it last moved, cells are only checked when
+    // they are accessed
+    checked: bool,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            code: vec![],
+            exits: vec![],
+            // run refuses to start off the tape
+            checked: true,
+        }
+    }
+}
+
+impl Assembler {
+    fn prologue(&mut self) {
+        // Five pushes on top of the return address keep rsp 16 byte aligned for calls,
+        // r15 is only saved for that
+        self.emit(&[0x53]); // push rbx
+        self.emit(&[0x41, 0x54]); // push r12
+        self.emit(&[0x41, 0x55]); // push r13
+        self.emit(&[0x41, 0x56]); // push r14
+        self.emit(&[0x41, 0x57]); // push r15
+        self.emit(&[0x48, 0x89, 0xfb]); // mov rbx, rdi
+        self.emit(&[0x49, 0x89, 0xf5]); // mov r13, rsi
+        self.emit(&[0x49, 0x89, 0xd4]); // mov r12, rdx
+        self.emit(&[0x49, 0x89, 0xce]); // mov r14, rcx
+    }
+
+    fn epilogue(&mut self) {
+        self.emit(&[0x31, 0xd2]); // xor edx, edx
+        self.emit(&[0xeb, 0x05]); // jmp past the failed exit
+        let failed = self.code.len();
+        for operand in std::mem::take(&mut self.exits) {
+            self.patch(operand, failed);
+        }
+        self.emit(&[0xba, 0x01, 0x00, 0x00, 0x00]); // mov edx, 1
+        self.emit(&[0x4c, 0x89, 0xe0]); // mov rax, r12
+        self.emit(&[0x41, 0x5f]); // pop r15
+        self.emit(&[0x41, 0x5e]); // pop r14
+        self.emit(&[0x41, 0x5d]); // pop r13
+        self.emit(&[0x41, 0x5c]); // pop r12
+        self.emit(&[0x5b]); // pop rbx
+        self.emit(&[0xc3]); // ret
+    }
+
+    fn block(&mut self, instrs: &[Instr]) {
+        for instr in instrs {
+            match instr {
+                Instr::Add(n) => {
+                    self.check();
+                    // add byte [r12], n
+                    self.emit(&[0x41, 0x80, 0x04, 0x24, *n as u8]);
+                }
+                Instr::Move(n) => {
+                    // add r12, n
+                    self.emit(&[0x49, 0x81, 0xc4]);
+                    self.emit(&(*n as i32).to_le_bytes());
+                    self.checked = false;
+                }
+                Instr::Out => {
+                    self.check();
+                    // mov rdi, r14; movzx esi, byte [r12]
+                    self.emit(&[0x4c, 0x89, 0xf7]);
+                    self.emit(&[0x41, 0x0f, 0xb6, 0x34, 0x24]);
+                    self.call(native_putchar as *const () as usize);
+                }
+                Instr::In => {
+                    self.check();
+                    // mov rdi, r14; movzx esi, byte [r12]
+                    self.emit(&[0x4c, 0x89, 0xf7]);
+                    self.emit(&[0x41, 0x0f, 0xb6, 0x34, 0x24]);
+                    self.call(native_getchar as *const () as usize);
+                    // mov byte [r12], al
+                    self.emit(&[0x41, 0x88, 0x04, 0x24]);
+                }
+                Instr::Loop(body) => {
+                    // Both conditions are checked, so the body starts and the code after
+                    // the loop continues on a checked cell
+                    self.check();
+                    // cmp byte [r12], 0; je past the loop
+                    self.emit(&[0x41, 0x80, 0x3c, 0x24, 0x00]);
+                    let skip = self.jump(&[0x0f, 0x84]);
+                    let start = self.code.len();
+                    self.block(body);
+                    self.check();
+                    // cmp byte [r12], 0; jne back to the body
+                    self.emit(&[0x41, 0x80, 0x3c, 0x24, 0x00]);
+                    let repeat = self.jump(&[0x0f, 0x85]);
+                    self.patch(repeat, start);
+                    let end = self.code.len();
+                    self.patch(skip, end);
+                }
+            }
+        }
+    }
+
+    // Leaves through the failed exit when r12 moved off of the tape since it was last checked
+    fn check(&mut self) {
+        if self.checked {
+            return;
+        }
+        // cmp r12, rbx; jb exit
+        self.emit(&[0x49, 0x39, 0xdc]);
+        let below = self.jump(&[0x0f, 0x82]);
+        // cmp r12, r13; jae exit
+        self.emit(&[0x4d, 0x39, 0xec]);
+        let above = self.jump(&[0x0f, 0x83]);
+        self.exits.extend([below, above]);
+        self.checked = true;
+    }
+
+    // mov rax, f; call rax
+    fn call(&mut self, f: usize) {
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&(f as u64).to_le_bytes());
+        self.emit(&[0xff, 0xd0]);
+    }
+
+    // Emits a jump with a placeholder rel32 and returns where the operand is
+    fn jump(&mut self, opcode: &[u8]) -> usize {
+        self.emit(opcode);
+        let operand = self.code.len();
+        self.emit(&[0; 4]);
+        operand
+    }
+
+    // Points the rel32 operand at target, relative to the end of the instruction
+    fn patch(&mut self, operand: usize, target: usize) {
+        let rel = target as i64 - (operand + 4) as i64;
+        self.code[operand..operand + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::TapeSnapshot;
+    use crate::tape::TapeConfig;
+
+    fn run_native(
+        source: &[u8],
+        config: &TapeConfig,
+        input: &[u8],
+        eof: Eof,
+    ) -> anyhow::Result<(Vec<u8>, TapeSnapshot)> {
+        let ast = Ast::parse(source).unwrap();
+        let mut cells = config.initial_cells();
+        let mut output = vec![];
+        let pointer = NativeJit::new(&ast)?.eof(eof).run(
+            &mut cells,
+            config.start,
+            &mut &input[..],
+            &mut output,
+        )?;
+        Ok((output, TapeSnapshot::new(0, cells, pointer)))
+    }
+
+    #[test]
+    fn pointer_may_end_off_the_tape() {
+        let (output, snapshot) =
+            run_native(b"<", &TapeConfig::default(), b"", Eof::MinusOne).unwrap();
+        assert!(output.is_empty());
+        assert_eq!(snapshot.pointer, -1);
+    }
+
+    #[test]
+    fn touching_a_cell_off_the_tape_fails() {
+        let error = run_native(b"<+", &TapeConfig::default(), b"", Eof::MinusOne).unwrap_err();
+        assert_eq!(error.to_string(), "pointer -1 is out of the tape bounds");
+    }
+
+    #[test]
+    fn starting_off_the_tape_fails() {
+        let jit = NativeJit::new(&Ast::parse(b"+").unwrap()).unwrap();
+        let error = jit
+            .run(&mut [0; 4], 4, &mut io::empty(), &mut io::sink())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "pointer 4 is out of the tape bounds");
+        assert!(jit
+            .run(&mut [], 0, &mut io::empty(), &mut io::sink())
+            .is_err());
+    }
+}
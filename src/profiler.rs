@@ -0,0 +1,271 @@
+use crate::ast::{LineIndex, Location};
+use std::fmt::{self, Write};
+
+const SNIPPET_LEN: usize = 40;
+
+/// Execution counters indexed by source offset, filled in by the Machine while it runs
+#[derive(Default)]
+pub struct Profiler {
+    counts: Vec<u64>,
+    iterations: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn record(&mut self, offset: usize) {
+        Self::bump(&mut self.counts, offset);
+    }
+
+    pub fn record_iteration(&mut self, offset: usize) {
+        Self::bump(&mut self.iterations, offset);
+    }
+
+    /// Instructions executed so far
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Summarizes the counters for source, the program the Machine ran
+    pub fn report(&self, source: &[u8]) -> Report {
+        // Built once so reporting stays linear in the size of the program
+        let lines = LineIndex::new(source);
+        let closing = closing_brackets(source);
+        let mut prefix = vec![0];
+        prefix.extend(self.counts.iter().scan(0, |sum, n| {
+            *sum += n;
+            Some(*sum)
+        }));
+
+        let mut loops = self
+            .iterations
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .map(|(start, n)| {
+                let end = closing[start];
+                LoopStat {
+                    location: lines.locate(start),
+                    iterations: *n,
+                    cost: prefix[(end + 1).min(self.counts.len())] - prefix[start],
+                    snippet: snippet(&source[start..=end]),
+                }
+            })
+            .collect::<Vec<LoopStat>>();
+        loops.sort_by(|a, b| {
+            b.cost
+                .cmp(&a.cost)
+                .then(a.location.offset.cmp(&b.location.offset))
+        });
+
+        let mut instructions = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .map(|(offset, n)| InstructionStat {
+                location: lines.locate(offset),
+                op: source[offset] as char,
+                count: *n,
+            })
+            .collect::<Vec<InstructionStat>>();
+        instructions.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(a.location.offset.cmp(&b.location.offset))
+        });
+
+        Report {
+            total: self.total(),
+            loops,
+            instructions,
+            limit: 10,
+        }
+    }
+
+    fn bump(counters: &mut Vec<u64>, offset: usize) {
+        if offset >= counters.len() {
+            counters.resize(offset + 1, 0);
+        }
+        counters[offset] += 1;
+    }
+}
+
+/// A loop and how often its body ran
+pub struct LoopStat {
+    pub location: Location,
+    pub iterations: u64,
+    /// Instructions executed inside the loop including nested loops
+    pub cost: u64,
+    pub snippet: String,
+}
+
+/// How often the instruction op at location ran
+pub struct InstructionStat {
+    pub location: Location,
+    pub op: char,
+    pub count: u64,
+}
+
+/// Hottest loops and instructions, most executed first
+pub struct Report {
+    pub total: u64,
+    pub loops: Vec<LoopStat>,
+    pub instructions: Vec<InstructionStat>,
+    limit: usize,
+}
+
+impl Report {
+    /// Maximum number of loops and instructions shown, 0 shows everything
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(out, "{{\"total\":{},\"loops\":[", self.total).unwrap();
+        for (i, l) in self.loops.iter().take(self.take()).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "{{\"offset\":{},\"line\":{},\"column\":{},\"iterations\":{},\"cost\":{},\"snippet\":\"{}\"}}",
+                l.location.offset, l.location.line, l.location.column, l.iterations, l.cost, l.snippet
+            )
+            .unwrap();
+        }
+        out.push_str("],\"instructions\":[");
+        for (i, ins) in self.instructions.iter().take(self.take()).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "{{\"offset\":{},\"line\":{},\"column\":{},\"op\":\"{}\",\"count\":{}}}",
+                ins.location.offset, ins.location.line, ins.location.column, ins.op, ins.count
+            )
+            .unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+
+    fn take(&self) -> usize {
+        match self.limit {
+            0 => usize::MAX,
+            n => n,
+        }
+    }
+
+    fn percent(&self, n: u64) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => n as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "total instructions executed: {}", self.total)?;
+
+        writeln!(f, "\nhot loops:")?;
+        writeln!(
+            f,
+            "{:>10} {:>14} {:>14} {:>7}  snippet",
+            "location", "iterations", "cost", "%"
+        )?;
+        for l in self.loops.iter().take(self.take()) {
+            writeln!(
+                f,
+                "{:>10} {:>14} {:>14} {:>6.2}%  {}",
+                l.location.to_string(),
+                l.iterations,
+                l.cost,
+                self.percent(l.cost),
+                l.snippet
+            )?;
+        }
+
+        writeln!(f, "\nhot instructions:")?;
+        writeln!(
+            f,
+            "{:>10} {:>3} {:>14} {:>7}",
+            "location", "op", "count", "%"
+        )?;
+        for ins in self.instructions.iter().take(self.take()) {
+            writeln!(
+                f,
+                "{:>10} {:>3} {:>14} {:>6.2}%",
+                ins.location.to_string(),
+                ins.op,
+                ins.count,
+                self.percent(ins.count)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Offset of the ] closing the [ at every offset, unclosed loops run to the end of source
+fn closing_brackets(source: &[u8]) -> Vec<usize> {
+    let mut closing = vec![source.len().saturating_sub(1); source.len()];
+    let mut open = vec![];
+    for (i, c) in source.iter().enumerate() {
+        match c {
+            b'[' => open.push(i),
+            b']' => {
+                if let Some(start) = open.pop() {
+                    closing[start] = i;
+                }
+            }
+            _ => {}
+        }
+    }
+    closing
+}
+
+// Only keeps the commands so comments and newlines don't clutter the report
+fn snippet(source: &[u8]) -> String {
+    let commands = source
+        .iter()
+        .filter(|c| b"+-<>.,[]".contains(c))
+        .map(|c| *c as char)
+        .collect::<String>();
+
+    if commands.len() > SNIPPET_LEN {
+        format!("{}...", &commands[..SNIPPET_LEN - 3])
+    } else {
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Ast;
+    use crate::machine::Machine;
+    use crate::tape::FixedTape;
+
+    #[test]
+    fn report_counts_loops_and_instructions() {
+        let source = b"++\n[->+<]";
+        let ast = Ast::parse(source).unwrap();
+        let mut machine = Machine::new(FixedTape::new(4)).with_profiler();
+        machine
+            .run_with_io(&ast, &mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+
+        let report = machine.profiler().unwrap().report(source);
+        // The [ is counted once on entry, the body once per iteration
+        assert_eq!(report.total, 2 + 1 + 2 * 4);
+        assert_eq!(report.loops.len(), 1);
+        let l = &report.loops[0];
+        assert_eq!((l.location.line, l.location.column), (2, 1));
+        assert_eq!(l.iterations, 2);
+        assert_eq!(l.cost, 1 + 2 * 4);
+        assert_eq!(l.snippet, "[->+<]");
+        assert_eq!(report.instructions[0].count, 2);
+        assert_eq!(report.instructions[0].location.offset, 4);
+    }
+}
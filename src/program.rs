@@ -0,0 +1,408 @@
+use crate::ast::Ast;
+#[cfg(feature = "llvm")]
+use crate::codegen::{self, Emit, IRCodegen};
+use crate::dump::TapeSnapshot;
+use crate::machine::Machine;
+#[cfg(all(target_arch = "x86_64", unix))]
+use crate::native::NativeJit;
+use crate::tape::{FixedTape, TapeConfig};
+use crate::transpile::{self, CellType, Eof, Language, TranspileOptions};
+use anyhow::anyhow;
+#[cfg(feature = "llvm")]
+use anyhow::bail;
+#[cfg(feature = "llvm")]
+use inkwell::OptimizationLevel;
+use std::io::{Read, Write};
+#[cfg(feature = "llvm")]
+use std::path::{Path, PathBuf};
+
+/// A parsed Brainfuck program and the tape it runs on, the entry point for using the
+/// crate as a library. Every way of executing the program is reachable from here:
+/// the interpreter with [`Program::run`], the built-in x86-64 JIT with
+/// [`Program::run_native`], the LLVM JIT with `Program::jit`, ahead of time
+/// compilation with `Program::compile` and the source backends with [`Program::transpile`].
+///
+/// Programs run on a fixed tape of [`TapeConfig::len`] byte cells, 30000 by default, and
+/// `,` stores -1 at the end of input unless [`Program::eof`] says otherwise.
+/// Failures, including accessing a cell outside of the tape, are returned as errors.
+pub struct Program {
+    ast: Ast,
+    source: Vec<u8>,
+    tape: TapeConfig,
+    opt_level: OptLevel,
+    eof: Eof,
+}
+
+/// How hard LLVM optimizes jitted and compiled code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    None,
+    Less,
+    #[default]
+    Default,
+    Aggressive,
+}
+
+impl Program {
+    /// Parses source, every byte other than the eight commands is a comment.
+    /// Fails with the line and column of the first unbalanced bracket.
+    pub fn parse(source: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            ast: Ast::parse(source)?,
+            source: source.to_vec(),
+            tape: TapeConfig::default(),
+            opt_level: OptLevel::default(),
+            eof: Eof::default(),
+        })
+    }
+
+    pub fn ast(&self) -> &Ast {
+        &self.ast
+    }
+
+    /// The bytes the program was parsed from, which Ast offsets point into.
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Length, starting cell and initial contents of the tape, checked when the program runs.
+    pub fn tape(mut self, tape: TapeConfig) -> Self {
+        self.tape = tape;
+        self
+    }
+
+    /// What `,` stores once input runs out, on every backend.
+    pub fn eof(mut self, eof: Eof) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Optimization level of `Program::jit` and `Program::compile`, the other
+    /// backends ignore it.
+    pub fn optimize(mut self, level: OptLevel) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Interprets the program with `,` reading from input and `.` writing to output and
+    /// returns the final tape.
+    pub fn run(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<TapeSnapshot> {
+        self.validate()?;
+        let mut machine = Machine::new(FixedTape::new(self.tape.len))
+            .with_config(&self.tape)?
+            .with_eof(self.eof);
+        machine.run_with_io(&self.ast, input, output)?;
+        output.flush()?;
+        Ok(machine.snapshot())
+    }
+
+    /// Runs the program as x86-64 machine code generated without LLVM and returns the
+    /// final tape. Other platforms fall back to [`Program::run`].
+    #[cfg(not(all(target_arch = "x86_64", unix)))]
+    pub fn run_native(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<TapeSnapshot> {
+        self.run(input, output)
+    }
+
+    /// Runs the program as x86-64 machine code generated without LLVM and returns the
+    /// final tape. Other platforms fall back to [`Program::run`].
+    #[cfg(all(target_arch = "x86_64", unix))]
+    pub fn run_native(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<TapeSnapshot> {
+        self.validate()?;
+        let mut cells = self.tape.initial_cells();
+        let pointer = NativeJit::new(&self.ast)?.eof(self.eof).run(
+            &mut cells,
+            self.tape.start,
+            input,
+            output,
+        )?;
+
+        Ok(TapeSnapshot::new(0, cells, pointer))
+    }
+
+    /// Compiles the program with LLVM's JIT, runs it with `,` reading from input and `.`
+    /// writing to output and returns the final tape.
+    ///
+    /// Cell accesses are bounds checked, touching a cell outside of the tape is returned as
+    /// an error naming the source offset of the instruction.
+    #[cfg(feature = "llvm")]
+    pub fn jit(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<TapeSnapshot> {
+        self.validate()?;
+        IRCodegen::from(&self.ast)
+            .tape_config(self.tape.clone())
+            .optimize(self.opt_level.into())
+            .jit_opt_level(self.opt_level.into())
+            .eof(self.eof)
+            .checked()
+            .dump_tape()
+            .jit_with_io(input, output)?
+            .ok_or_else(|| anyhow!("the jit did not hand back the tape"))
+    }
+
+    /// Compiles the program ahead of time and writes the artifacts listed in options.
+    #[cfg(feature = "llvm")]
+    pub fn compile(&self, options: &CompileOptions) -> anyhow::Result<()> {
+        self.validate()?;
+        if options.artifacts.is_empty() {
+            bail!("no artifacts to write, add some with CompileOptions::emit");
+        }
+
+        let codegen = IRCodegen::from(&self.ast)
+            .tape_config(self.tape.clone())
+            .optimize(self.opt_level.into())
+            .eof(self.eof);
+        let codegen = match &options.target {
+            Some(triple) => codegen.target(triple),
+            None => codegen,
+        };
+        let codegen = match &options.cpu {
+            Some(cpu) => codegen.cpu(cpu),
+            None => codegen,
+        };
+        let codegen = match &options.features {
+            Some(features) => codegen.features(features),
+            None => codegen,
+        };
+        let codegen = match &options.library {
+            Some(name) => codegen.library(name),
+            None => codegen,
+        };
+        let codegen = match &options.debug_info {
+            Some(path) => codegen.debug_info(path, &self.source),
+            None => codegen,
+        };
+        codegen.emit(&options.artifacts)
+    }
+
+    /// Translates the program to source code or a module in language with cells of the
+    /// given width.
+    pub fn transpile(&self, language: Language, cell: CellType) -> anyhow::Result<Vec<u8>> {
+        self.validate()?;
+        let options = TranspileOptions {
+            tape: self.tape.clone(),
+            cell,
+            eof: self.eof,
+            circular: false,
+        };
+        Ok(transpile::transpile(&self.ast, language, &options))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.tape
+            .validate()
+            .map_err(|e| anyhow!("invalid tape configuration: {}", e))
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl From<OptLevel> for OptimizationLevel {
+    fn from(level: OptLevel) -> Self {
+        match level {
+            OptLevel::None => OptimizationLevel::None,
+            OptLevel::Less => OptimizationLevel::Less,
+            OptLevel::Default => OptimizationLevel::Default,
+            OptLevel::Aggressive => OptimizationLevel::Aggressive,
+        }
+    }
+}
+
+/// What `Program::compile` writes and the machine it generates code for, the host
+/// unless a target is given.
+#[cfg(feature = "llvm")]
+#[derive(Clone, Default)]
+pub struct CompileOptions {
+    artifacts: Vec<(Emit, Option<PathBuf>)>,
+    target: Option<String>,
+    cpu: Option<String>,
+    features: Option<String>,
+    library: Option<String>,
+    debug_info: Option<PathBuf>,
+}
+
+#[cfg(feature = "llvm")]
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes kind to path, or to stdout when path is None. Executables are linked with
+    /// `$CC` or `cc` and libraries archived with `$AR` or `ar`.
+    pub fn emit(mut self, kind: Emit, path: Option<&Path>) -> Self {
+        self.artifacts.push((kind, path.map(Path::to_path_buf)));
+        self
+    }
+
+    /// Target triple such as `aarch64-unknown-linux-gnu` or `wasm32-unknown-unknown`.
+    pub fn target(mut self, triple: &str) -> Self {
+        self.target = Some(triple.to_string());
+        self
+    }
+
+    /// Target cpu, defaults to the host cpu or generic when cross compiling.
+    pub fn cpu(mut self, cpu: &str) -> Self {
+        self.cpu = Some(cpu.to_string());
+        self
+    }
+
+    /// Comma separated target features such as `+neon,-sve`.
+    pub fn features(mut self, features: &str) -> Self {
+        self.features = Some(features.to_string());
+        self
+    }
+
+    /// Compiles to the reentrant C function `bf_<name>` declared by [`Emit::Header`]
+    /// instead of a main function. Fails unless name is a C identifier.
+    pub fn library(mut self, name: &str) -> anyhow::Result<Self> {
+        codegen::validate_identifier(name)?;
+        self.library = Some(name.to_string());
+        Ok(self)
+    }
+
+    /// Emits DWARF line tables, path is the source file debuggers show.
+    pub fn debug_info(mut self, path: &Path) -> Self {
+        self.debug_info = Some(path.to_path_buf());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Run = fn(&Program, &mut dyn Read, &mut dyn Write) -> anyhow::Result<TapeSnapshot>;
+
+    // Every facade backend that is built on this platform
+    fn backends() -> Vec<(&'static str, Run)> {
+        vec![
+            ("run", Program::run),
+            ("run_native", Program::run_native),
+            #[cfg(feature = "llvm")]
+            ("jit", Program::jit),
+        ]
+    }
+
+    // Runs source on every backend with each EOF behaviour and checks they all match the
+    // interpreter, returns whether the interpreter ran it without an error
+    fn assert_agree(source: &[u8], tape: &TapeConfig, input: &[u8]) -> bool {
+        let mut ok = true;
+        for eof in [Eof::MinusOne, Eof::Zero, Eof::Unchanged] {
+            let program = Program::parse(source).unwrap().tape(tape.clone()).eof(eof);
+            let mut expected_output = vec![];
+            let expected = program.run(&mut &input[..], &mut expected_output);
+            ok &= expected.is_ok();
+            for (name, run) in backends() {
+                let mut output = vec![];
+                let actual = run(&program, &mut &input[..], &mut output);
+                let context = format!(
+                    "{} on {} with {:?}",
+                    name,
+                    String::from_utf8_lossy(source),
+                    eof
+                );
+                match (actual, &expected) {
+                    (Ok(actual), Ok(expected)) => {
+                        assert_eq!(&actual, expected, "{}", context);
+                        assert_eq!(output, expected_output, "{}", context);
+                    }
+                    (Err(_), Err(_)) => {}
+                    (actual, _) => panic!("{} returned {:?}", context, actual.map(|_| ())),
+                }
+            }
+        }
+        ok
+    }
+
+    #[test]
+    fn backends_agree_at_the_tape_edges() {
+        let tape = TapeConfig {
+            len: 4,
+            start: 0,
+            contents: vec![],
+        };
+        let programs: &[(&[u8], bool)] = &[
+            (b"<>+", true),
+            (b"<", true),
+            (b"<+", false),
+            (b">>>>.", false),
+            (b"+[<]", false),
+            (b">>>+[<]>.", true),
+            (b"++[>+++<-]>.", true),
+        ];
+        for (source, ok) in programs {
+            let context = String::from_utf8_lossy(source);
+            assert_eq!(assert_agree(source, &tape, b""), *ok, "{}", context);
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_a_configured_tape() {
+        let tape = TapeConfig {
+            len: 4,
+            start: 1,
+            contents: vec![3, 0, 2],
+        };
+        let programs: &[&[u8]] = &[
+            b">>>",
+            b">>>+",
+            b"<<",
+            b"<<.",
+            b"<,",
+            b"<[->+<]>.",
+            b">[<+>-]<.",
+            b"<[>]",
+            b"[>]+",
+            b"+[<+]",
+            b"<[->>>+<<<]>>>[-<+>]<.",
+        ];
+        for source in programs {
+            assert_agree(source, &tape, b"");
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_input() {
+        // Some of these read past the end of their input
+        let programs: &[(&[u8], &[u8])] = &[
+            (b",.", b"a"),
+            (b",.>,.", b"a"),
+            (b",+.,+.,+.", b"abc"),
+            (b">,>,>,[.<]", b"xyz"),
+            (b",>,[-<+>]<.", b"\x10\x20"),
+            (b"+++,,.", b"a"),
+            (b",.,.,.,.", b"ab"),
+        ];
+        for (source, input) in programs {
+            assert!(assert_agree(source, &TapeConfig::default(), input));
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_the_examples() {
+        let tape = TapeConfig::default();
+        assert!(assert_agree(
+            include_bytes!("../exmaples/helloworld.bf"),
+            &tape,
+            b""
+        ));
+        assert!(assert_agree(
+            include_bytes!("../exmaples/one.bf"),
+            &tape,
+            b""
+        ));
+    }
+}
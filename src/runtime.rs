@@ -0,0 +1,376 @@
+use crate::dump::TapeSnapshot;
+use anyhow::anyhow;
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+
+// C implementation of the runtime, compiled alongside the IR by clang
+pub const C_SOURCE: &str = include_str!("runtime/tape.c");
+
+// Layout of the tape global emitted by IRCodegen, matches struct bf_tape in runtime/tape.c
+#[repr(C)]
+pub struct RuntimeTape {
+    start: *mut u8,
+    end: *mut u8,
+    // Cells between start and the leftmost cell of the initial tape
+    origin: i64,
+    // Whether start was allocated by bf_tape_grow rather than being the memory global
+    owned: i64,
+}
+
+thread_local! {
+    // Filled in by bf_tape_dump for the JIT to hand back once main returns
+    static SNAPSHOT: RefCell<Option<TapeSnapshot>> = const { RefCell::new(None) };
+    // Why main is about to return 1, set by the runtime functions that fail
+    static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Turns the status main returned into the snapshot or the error the runtime recorded
+pub fn finish(status: i32) -> anyhow::Result<Option<TapeSnapshot>> {
+    let snapshot = SNAPSHOT.with(|s| s.borrow_mut().take());
+    let error = ERROR.with(|e| e.borrow_mut().take());
+    match status {
+        0 => Ok(snapshot),
+        _ => Err(anyhow!(error.unwrap_or_else(|| format!(
+            "program failed with status {}",
+            status
+        )))),
+    }
+}
+
+fn set_error(message: String) {
+    ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+type HostIo = (*mut (dyn Read + 'static), *mut (dyn Write + 'static));
+
+thread_local! {
+    // Streams bf_host_getchar, bf_host_putchar and bf_host_write use while with_host_io runs
+    static HOST_IO: Cell<Option<HostIo>> = const { Cell::new(None) };
+}
+
+// Clears HOST_IO even when f panics so the callbacks never see dangling streams
+struct HostIoGuard;
+
+impl Drop for HostIoGuard {
+    fn drop(&mut self) {
+        HOST_IO.with(|io| io.set(None));
+    }
+}
+
+// Runs f with the host callbacks reading from input and writing to output
+pub fn with_host_io<R>(input: &mut dyn Read, output: &mut dyn Write, f: impl FnOnce() -> R) -> R {
+    // The lifetimes are erased but the pointers are only used until the guard drops
+    let io: HostIo = unsafe {
+        (
+            std::mem::transmute::<*mut dyn Read, *mut (dyn Read + 'static)>(input),
+            std::mem::transmute::<*mut dyn Write, *mut (dyn Write + 'static)>(output),
+        )
+    };
+    HOST_IO.with(|cell| cell.set(Some(io)));
+    let guard = HostIoGuard;
+    let result = f();
+    drop(guard);
+
+    let _ = output.flush();
+    result
+}
+
+fn host_io() -> HostIo {
+    HOST_IO
+        .with(|io| io.get())
+        .expect("host I/O callback called outside of with_host_io")
+}
+
+// Replaces getchar, returns -1 like EOF once input runs out
+pub extern "C" fn bf_host_getchar() -> i32 {
+    let mut buf = [0u8; 1];
+    match unsafe { (*host_io().0).read(&mut buf) } {
+        Ok(1) => buf[0] as i32,
+        _ => -1,
+    }
+}
+
+// Replaces putchar
+pub extern "C" fn bf_host_putchar(c: i32) -> i32 {
+    match unsafe { (*host_io().1).write_all(&[c as u8]) } {
+        Ok(()) => c,
+        Err(_) => -1,
+    }
+}
+
+// Replaces write(2) used by the buffered output of the generated code, fd is always stdout
+pub extern "C" fn bf_host_write(_fd: i32, buf: *const u8, len: usize) -> isize {
+    let bytes = unsafe { std::slice::from_raw_parts(buf, len) };
+    match unsafe { (*host_io().1).write_all(bytes) } {
+        Ok(()) => len as isize,
+        Err(_) => -1,
+    }
+}
+
+// JIT counterpart of bf_tape_grow in runtime/tape.c, called whenever the pointer leaves the tape.
+// Returns null once the tape would exceed cap or cannot be allocated
+pub extern "C" fn bf_tape_grow(cur: *mut u8, tape: *mut RuntimeTape, cap: i64) -> *mut u8 {
+    unsafe {
+        let tape = &mut *tape;
+        let len = tape.end as i64 - tape.start as i64;
+        let idx = cur as i64 - tape.start as i64;
+        let left = if idx < 0 { -idx } else { 0 };
+        let right = if idx >= len { idx - len + 1 } else { 0 };
+
+        if len + left + right > cap {
+            set_error(format!("tape exceeded the memory cap of {} cells", cap));
+            return std::ptr::null_mut();
+        }
+
+        let extra = (left + right).max(len).min(cap - len);
+
+        let layout = Layout::array::<u8>((len + extra) as usize).unwrap();
+        let cells = alloc::alloc_zeroed(layout);
+        if cells.is_null() {
+            set_error(format!("unable to allocate {} tape cells", len + extra));
+            return cells;
+        }
+
+        let shift = if left > 0 { extra } else { 0 };
+        std::ptr::copy_nonoverlapping(tape.start, cells.add(shift as usize), len as usize);
+        if tape.owned != 0 {
+            alloc::dealloc(tape.start, Layout::array::<u8>(len as usize).unwrap());
+        }
+
+        tape.start = cells;
+        tape.end = cells.add((len + extra) as usize);
+        tape.origin += shift;
+        tape.owned = 1;
+        cells.offset((shift + idx) as isize)
+    }
+}
+
+// JIT counterpart of bf_tape_free in runtime/tape.c, releases the cells bf_tape_grow allocated
+pub extern "C" fn bf_tape_free(tape: *mut RuntimeTape) {
+    unsafe {
+        let tape = &mut *tape;
+        if tape.owned != 0 {
+            let len = tape.end as usize - tape.start as usize;
+            alloc::dealloc(tape.start, Layout::array::<u8>(len).unwrap());
+            tape.owned = 0;
+        }
+    }
+}
+
+// JIT counterpart of bf_tape_dump in runtime/tape.c, keeps the tape for finish
+pub extern "C" fn bf_tape_dump(tape: *const RuntimeTape, ptr: *const u8) {
+    let snapshot = unsafe {
+        let tape = &*tape;
+        let len = tape.end as usize - tape.start as usize;
+        TapeSnapshot::new(
+            -tape.origin as isize,
+            std::slice::from_raw_parts(tape.start, len).to_vec(),
+            (ptr as isize - tape.start as isize) - tape.origin as isize,
+        )
+    };
+    SNAPSHOT.with(|s| *s.borrow_mut() = Some(snapshot));
+}
+
+// JIT counterpart of bf_bounds_error in runtime/tape.c, main returns 1 right after it
+pub extern "C" fn bf_bounds_error(offset: i64, cell: i64) {
+    set_error(format!(
+        "pointer out of bounds at source offset {}: cell {}",
+        offset, cell
+    ));
+}
+
+#[cfg(unix)]
+mod guard {
+    use super::RuntimeTape;
+    use std::mem::MaybeUninit;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+    // Room on each side of the tape for moves that skip several cells at once
+    const GUARD_SIZE: usize = 64 * 1024;
+
+    static TAPE: AtomicPtr<RuntimeTape> = AtomicPtr::new(ptr::null_mut());
+    // Where the cells lived before they were moved into the mapping
+    static MEMORY: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+    static LO: AtomicUsize = AtomicUsize::new(0);
+    static HI: AtomicUsize = AtomicUsize::new(0);
+
+    // Handlers installed before bf_guard_tape, such as Rust's stack overflow handler
+    static mut OLD_SEGV: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+    static mut OLD_BUS: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+
+    fn old_action(sig: libc::c_int) -> *mut libc::sigaction {
+        match sig {
+            libc::SIGSEGV => ptr::addr_of_mut!(OLD_SEGV).cast(),
+            _ => ptr::addr_of_mut!(OLD_BUS).cast(),
+        }
+    }
+
+    // Only calls async signal safe functions, the message is formatted on the stack.
+    // Output still sitting in stdio buffers or the program's out_buf is lost
+    extern "C" fn handler(sig: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+        unsafe {
+            let addr = (*info).si_addr() as usize;
+            if addr < LO.load(Ordering::Relaxed) || addr >= HI.load(Ordering::Relaxed) {
+                // Not ours, returning retries the access under the previous handler
+                libc::sigaction(sig, old_action(sig), ptr::null_mut());
+                return;
+            }
+
+            let tape = &*TAPE.load(Ordering::Relaxed);
+            let cell = (addr as i64 - tape.start as i64) - tape.origin;
+
+            let mut message = [0u8; 64];
+            let prefix = b"pointer out of bounds: cell ";
+            message[..prefix.len()].copy_from_slice(prefix);
+            let mut len = prefix.len();
+            if cell < 0 {
+                message[len] = b'-';
+                len += 1;
+            }
+            let mut digits = [0u8; 20];
+            let mut n = cell.unsigned_abs();
+            let mut count = 0;
+            loop {
+                digits[count] = b'0' + (n % 10) as u8;
+                count += 1;
+                n /= 10;
+                if n == 0 {
+                    break;
+                }
+            }
+            for digit in digits[..count].iter().rev() {
+                message[len] = *digit;
+                len += 1;
+            }
+            message[len] = b'\n';
+            len += 1;
+
+            libc::write(
+                libc::STDERR_FILENO,
+                message.as_ptr() as *const libc::c_void,
+                len,
+            );
+            libc::_exit(1);
+        }
+    }
+
+    // JIT counterpart of bf_guard_tape in runtime/tape.c, moves the tape into a mapping
+    // with PROT_NONE guard pages on both sides and reports faults inside of them.
+    // Returns 1 and leaves the tape alone when the mapping cannot be created
+    pub extern "C" fn bf_guard_tape(tape: *mut RuntimeTape) -> i32 {
+        unsafe {
+            let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            let guard = GUARD_SIZE.div_ceil(page) * page;
+            let len = (*tape).end as usize - (*tape).start as usize;
+            let body = len.div_ceil(page) * page;
+
+            let map = libc::mmap(
+                ptr::null_mut(),
+                guard * 2 + body,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if map == libc::MAP_FAILED {
+                super::set_error(format!(
+                    "unable to map guarded tape: {}",
+                    std::io::Error::last_os_error()
+                ));
+                return 1;
+            }
+            if libc::mprotect(map.add(guard), body, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+                super::set_error(format!(
+                    "unable to map guarded tape: {}",
+                    std::io::Error::last_os_error()
+                ));
+                libc::munmap(map, guard * 2 + body);
+                return 1;
+            }
+
+            // Starts right after the lower guard so stepping left of the first cell faults,
+            // only moves past the last cell into the rest of its page go unnoticed
+            let map = map as *mut u8;
+            let cells = map.add(guard);
+            ptr::copy_nonoverlapping((*tape).start, cells, len);
+            MEMORY.store((*tape).start, Ordering::Relaxed);
+            (*tape).start = cells;
+            (*tape).end = cells.add(len);
+
+            TAPE.store(tape, Ordering::Relaxed);
+            LO.store(map as usize, Ordering::Relaxed);
+            HI.store(map as usize + guard * 2 + body, Ordering::Relaxed);
+
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as *const () as usize;
+            // Runs on the alternate stack Rust sets up so stack overflows still reach it
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGSEGV, &action, old_action(libc::SIGSEGV));
+            libc::sigaction(libc::SIGBUS, &action, old_action(libc::SIGBUS));
+            0
+        }
+    }
+
+    // JIT counterpart of bf_unguard_tape in runtime/tape.c, called before main returns.
+    // Copies the cells back to where they were, unmaps the guarded tape and puts the
+    // previous signal handlers back
+    pub extern "C" fn bf_unguard_tape(tape: *mut RuntimeTape) {
+        unsafe {
+            let map = LO.swap(0, Ordering::Relaxed);
+            if map == 0 {
+                return;
+            }
+            libc::sigaction(libc::SIGSEGV, old_action(libc::SIGSEGV), ptr::null_mut());
+            libc::sigaction(libc::SIGBUS, old_action(libc::SIGBUS), ptr::null_mut());
+
+            let len = (*tape).end as usize - (*tape).start as usize;
+            let memory = MEMORY.swap(ptr::null_mut(), Ordering::Relaxed);
+            ptr::copy_nonoverlapping((*tape).start, memory, len);
+            (*tape).start = memory;
+            (*tape).end = memory.add(len);
+
+            let hi = HI.swap(0, Ordering::Relaxed);
+            TAPE.store(ptr::null_mut(), Ordering::Relaxed);
+            libc::munmap(map as *mut libc::c_void, hi - map);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use guard::{bf_guard_tape, bf_unguard_tape};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::process::Command;
+
+    #[test]
+    fn c_source_builds_as_strict_c99() {
+        let dir = std::env::temp_dir().join(format!("bf_runtime_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tape.c"), C_SOURCE).unwrap();
+        let output = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-c", "-o"])
+            .arg(dir.join("tape.o"))
+            .arg(dir.join("tape.c"))
+            .output();
+        std::fs::remove_dir_all(&dir).unwrap();
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("skipped, cc is not installed")
+            }
+            output => {
+                let output = output.unwrap();
+                assert!(
+                    output.status.success(),
+                    "{}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// Runtime support linked into compiled brainfuck programs, mirrors src/runtime.rs
+
+// sigaction and siginfo_t are POSIX and MAP_ANONYMOUS an extension, none of which strict
+// -std=c99 declares without these, they have to come before the first system header
+#define _POSIX_C_SOURCE 200809L
+#define _DEFAULT_SOURCE
+#define _DARWIN_C_SOURCE
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+// Layout of the tape global emitted by IRCodegen
+struct bf_tape {
+    uint8_t *start;
+    uint8_t *end;
+    // Cells between start and the leftmost cell of the initial tape
+    int64_t origin;
+    // Whether start was allocated by bf_tape_grow rather than being the memory global
+    int64_t owned;
+};
+
+// Called whenever the pointer leaves [tape->start, tape->end).
+// Returns the pointer to the same cell inside the reallocated tape, or NULL after reporting
+// that the tape would exceed cap or cannot be allocated.
+uint8_t *bf_tape_grow(uint8_t *cur, struct bf_tape *tape, int64_t cap) {
+    int64_t len = tape->end - tape->start;
+    int64_t idx = (int64_t)((intptr_t)cur - (intptr_t)tape->start);
+    int64_t left = idx < 0 ? -idx : 0;
+    int64_t right = idx >= len ? idx - len + 1 : 0;
+
+    if (len + left + right > cap) {
+        fprintf(stderr, "tape exceeded the memory cap of %lld cells\n", (long long)cap);
+        return NULL;
+    }
+
+    int64_t extra = left + right;
+    if (extra < len) extra = len;
+    if (len + extra > cap) extra = cap - len;
+
+    uint8_t *cells = calloc(len + extra, 1);
+    if (!cells) {
+        fprintf(stderr, "unable to allocate %lld tape cells\n", (long long)(len + extra));
+        return NULL;
+    }
+
+    int64_t shift = left ? extra : 0;
+    memcpy(cells + shift, tape->start, len);
+    if (tape->owned) {
+        free(tape->start);
+    }
+
+    tape->start = cells;
+    tape->end = cells + len + extra;
+    tape->origin += shift;
+    tape->owned = 1;
+    return cells + shift + idx;
+}
+
+// Called before main returns, releases the cells bf_tape_grow allocated
+void bf_tape_free(struct bf_tape *tape) {
+    if (tape->owned) {
+        free(tape->start);
+        tape->owned = 0;
+    }
+}
+
+// Mirrors the formats of TapeSnapshot in src/dump.rs
+static void bf_tape_dump_hex(FILE *out, struct bf_tape *tape, int64_t pointer) {
+    int64_t first = -tape->origin;
+    int64_t last = first + (tape->end - tape->start);
+    // Stays on the tape unless the pointer itself fell off of it
+    int64_t from = pointer - 128;
+    int64_t to = pointer + 128;
+    int64_t lo = first < pointer ? first : pointer;
+    int64_t hi = last - 1 > pointer ? last - 1 : pointer;
+    if (from < lo) from = lo;
+    if (to > hi) to = hi;
+    from = (from >= 0 ? from / 16 : (from - 15) / 16) * 16;
+    to = (to >= 0 ? to / 16 : (to - 15) / 16) * 16 + 16;
+
+    fprintf(out, "pointer: %lld\n", (long long)pointer);
+    for (int64_t row = from; row < to; row += 16) {
+        fprintf(out, "%8lld:", (long long)row);
+        for (int64_t idx = row; idx < row + 16; idx++) {
+            uint8_t value = idx >= first && idx < last ? tape->start[idx - first] : 0;
+            fprintf(out, idx == pointer ? "[%02x]" : " %02x ", value);
+        }
+        fputc('\n', out);
+    }
+}
+
+static void bf_tape_dump_json(FILE *out, struct bf_tape *tape, int64_t pointer) {
+    fprintf(out, "{\"first\":%lld,\"pointer\":%lld,\"cells\":[", (long long)-tape->origin,
+            (long long)pointer);
+    for (uint8_t *cell = tape->start; cell < tape->end; cell++) {
+        fprintf(out, cell == tape->start ? "%u" : ",%u", *cell);
+    }
+    fputs("]}\n", out);
+}
+
+// Called before main returns, writes the tape to $BF_TAPE_DUMP in $BF_TAPE_DUMP_FORMAT
+// (hex, binary or json, defaults to hex), "-" writes to stderr
+void bf_tape_dump(struct bf_tape *tape, uint8_t *ptr) {
+    const char *path = getenv("BF_TAPE_DUMP");
+    const char *format = getenv("BF_TAPE_DUMP_FORMAT");
+    if (!path) {
+        return;
+    }
+
+    FILE *out = strcmp(path, "-") == 0 ? stderr : fopen(path, "wb");
+    if (!out) {
+        fprintf(stderr, "unable to open tape dump %s\n", path);
+        return;
+    }
+
+    int64_t pointer = (ptr - tape->start) - tape->origin;
+    if (format && strcmp(format, "binary") == 0) {
+        fwrite(tape->start, 1, tape->end - tape->start, out);
+    } else if (format && strcmp(format, "json") == 0) {
+        bf_tape_dump_json(out, tape, pointer);
+    } else {
+        bf_tape_dump_hex(out, tape, pointer);
+    }
+
+    if (out != stderr) {
+        fclose(out);
+    }
+}
+
+// Called by programs compiled with bounds checks when a move at source offset leaves the tape,
+// main returns 1 right after it
+void bf_bounds_error(int64_t offset, int64_t cell) {
+    fflush(stdout);
+    fprintf(stderr, "pointer out of bounds at source offset %lld: cell %lld\n", (long long)offset,
+            (long long)cell);
+}
+
+#if defined(__unix__) || defined(__APPLE__)
+#include <signal.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+// Room on each side of the tape for moves that skip several cells at once
+#define BF_GUARD_SIZE (64 * 1024)
+
+static struct bf_tape *bf_guarded_tape = NULL;
+// Where the cells lived before they were moved into the mapping
+static uint8_t *bf_guard_memory = NULL;
+static uint8_t *bf_guard_lo = NULL;
+static uint8_t *bf_guard_hi = NULL;
+// Handlers installed before bf_guard_tape
+static struct sigaction bf_old_segv;
+static struct sigaction bf_old_bus;
+
+// Only calls async signal safe functions, output still sitting in stdio buffers or the
+// program's out_buf is lost
+static void bf_guard_handler(int sig, siginfo_t *info, void *context) {
+    (void)context;
+    uint8_t *addr = info->si_addr;
+    if (addr < bf_guard_lo || addr >= bf_guard_hi) {
+        // Not ours, returning retries the access under the previous handler
+        sigaction(sig, sig == SIGSEGV ? &bf_old_segv : &bf_old_bus, NULL);
+        return;
+    }
+
+    int64_t cell = (int64_t)((intptr_t)addr - (intptr_t)bf_guarded_tape->start) -
+                   bf_guarded_tape->origin;
+    char message[64] = "pointer out of bounds: cell ";
+    size_t len = strlen(message);
+    if (cell < 0) {
+        message[len++] = '-';
+    }
+    char digits[20];
+    int count = 0;
+    uint64_t n = cell < 0 ? -(uint64_t)cell : (uint64_t)cell;
+    do {
+        digits[count++] = '0' + n % 10;
+        n /= 10;
+    } while (n);
+    while (count) {
+        message[len++] = digits[--count];
+    }
+    message[len++] = '\n';
+    write(STDERR_FILENO, message, len);
+    _exit(1);
+}
+
+// Moves the tape into a mapping with PROT_NONE guard pages on both sides. The tape starts right
+// after the lower guard, cells right of the last one are only caught past the page holding it.
+// Returns 1 and leaves the tape alone when the mapping cannot be created.
+int bf_guard_tape(struct bf_tape *tape) {
+    size_t page = sysconf(_SC_PAGESIZE);
+    size_t guard = (BF_GUARD_SIZE + page - 1) / page * page;
+    size_t len = tape->end - tape->start;
+    size_t body = (len + page - 1) / page * page;
+
+    uint8_t *map = mmap(NULL, guard * 2 + body, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (map == MAP_FAILED) {
+        perror("unable to map guarded tape");
+        return 1;
+    }
+    if (mprotect(map + guard, body, PROT_READ | PROT_WRITE) != 0) {
+        perror("unable to map guarded tape");
+        munmap(map, guard * 2 + body);
+        return 1;
+    }
+
+    uint8_t *cells = map + guard;
+    memcpy(cells, tape->start, len);
+    bf_guard_memory = tape->start;
+    tape->start = cells;
+    tape->end = cells + len;
+
+    bf_guarded_tape = tape;
+    bf_guard_lo = map;
+    bf_guard_hi = map + guard * 2 + body;
+
+    struct sigaction action;
+    memset(&action, 0, sizeof(action));
+    action.sa_sigaction = bf_guard_handler;
+    action.sa_flags = SA_SIGINFO | SA_ONSTACK;
+    sigemptyset(&action.sa_mask);
+    sigaction(SIGSEGV, &action, &bf_old_segv);
+    sigaction(SIGBUS, &action, &bf_old_bus);
+    return 0;
+}
+
+// Called before main returns, copies the cells back, unmaps the guarded tape and puts the
+// previous signal handlers back
+void bf_unguard_tape(struct bf_tape *tape) {
+    if (!bf_guard_lo) {
+        return;
+    }
+    sigaction(SIGSEGV, &bf_old_segv, NULL);
+    sigaction(SIGBUS, &bf_old_bus, NULL);
+
+    size_t len = tape->end - tape->start;
+    memcpy(bf_guard_memory, tape->start, len);
+    tape->start = bf_guard_memory;
+    tape->end = bf_guard_memory + len;
+
+    munmap(bf_guard_lo, bf_guard_hi - bf_guard_lo);
+    bf_guarded_tape = NULL;
+    bf_guard_memory = NULL;
+    bf_guard_lo = NULL;
+    bf_guard_hi = NULL;
+}
+#endif
@@ -0,0 +1,147 @@
+use crate::ast::{Ast, Operator};
+use crate::transpile::{lower, Instr};
+use std::fmt::{self, Write};
+
+/// Static counts over a parsed program, nothing is executed
+pub struct Stats {
+    pub bytes: usize,
+    pub commands: usize,
+    /// How often each of + - < > . , [ appears, in that order
+    pub counts: [usize; 7],
+    pub max_depth: usize,
+    /// Instructions left once runs of +- and <> are folded, what the backends generate code for
+    pub folded: usize,
+}
+
+const COMMANDS: [char; 7] = ['+', '-', '<', '>', '.', ',', '['];
+
+impl Stats {
+    /// Counts the commands of source and the instructions ast lowers to
+    pub fn new(source: &[u8], ast: &Ast) -> Self {
+        let mut stats = Self {
+            bytes: source.len(),
+            commands: 0,
+            counts: [0; 7],
+            max_depth: 0,
+            folded: folded(&lower(ast)),
+        };
+        stats.count(ast, 1);
+        // Every loop is a [ and a ]
+        stats.commands = stats.counts.iter().sum::<usize>() + stats.counts[6];
+        stats
+    }
+
+    fn count(&mut self, ast: &Ast, depth: usize) {
+        for op in ast.inner() {
+            let i = match op {
+                Operator::Inc => 0,
+                Operator::Dec => 1,
+                Operator::DecPtr => 2,
+                Operator::IncPtr => 3,
+                Operator::Out => 4,
+                Operator::In => 5,
+                Operator::Loop(body) => {
+                    self.max_depth = self.max_depth.max(depth);
+                    self.count(body, depth + 1);
+                    6
+                }
+            };
+            self.counts[i] += 1;
+        }
+    }
+
+    /// Number of `[` in the source, every loop has exactly one
+    pub fn loops(&self) -> usize {
+        self.counts[6]
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(
+            out,
+            "{{\"bytes\":{},\"commands\":{},\"comments\":{},\"counts\":{{",
+            self.bytes,
+            self.commands,
+            self.bytes - self.commands
+        )
+        .unwrap();
+        for (i, (c, n)) in COMMANDS.iter().zip(self.counts).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{}\":{}", c, n).unwrap();
+        }
+        write!(
+            out,
+            "}},\"loops\":{},\"max_depth\":{},\"folded\":{}}}",
+            self.loops(),
+            self.max_depth,
+            self.folded
+        )
+        .unwrap();
+        out
+    }
+}
+
+fn folded(instrs: &[Instr]) -> usize {
+    instrs
+        .iter()
+        .map(|instr| match instr {
+            Instr::Loop(body) => 1 + folded(body),
+            _ => 1,
+        })
+        .sum()
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<22}{:>10}", "bytes", self.bytes)?;
+        writeln!(f, "{:<22}{:>10}", "commands", self.commands)?;
+        writeln!(
+            f,
+            "{:<22}{:>10}",
+            "comment bytes",
+            self.bytes - self.commands
+        )?;
+        for (c, n) in COMMANDS.iter().zip(self.counts) {
+            let name = match c {
+                '[' => "  [ ]".to_string(),
+                c => format!("  {}", c),
+            };
+            writeln!(f, "{:<22}{:>10}", name, n)?;
+        }
+        writeln!(f, "{:<22}{:>10}", "loops", self.loops())?;
+        writeln!(f, "{:<22}{:>10}", "max loop depth", self.max_depth)?;
+        writeln!(f, "{:<22}{:>10}", "folded instructions", self.folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(source: &[u8]) -> Stats {
+        Stats::new(source, &Ast::parse(source).unwrap())
+    }
+
+    #[test]
+    fn counts() {
+        let stats = stats(b"add ++[->+[-]<] print .,");
+        assert_eq!(stats.bytes, 24);
+        assert_eq!(stats.commands, 13);
+        assert_eq!(stats.counts, [3, 2, 1, 1, 1, 1, 2]);
+        assert_eq!(stats.loops(), 2);
+        assert_eq!(stats.max_depth, 2);
+        // ++ folds into one instruction
+        assert_eq!(stats.folded, 10);
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            stats(b"+ [<]").to_json(),
+            "{\"bytes\":5,\"commands\":4,\"comments\":1,\"counts\":{\"+\":1,\"-\":0,\"<\":1,\
+             \">\":0,\".\":0,\",\":0,\"[\":1},\"loops\":1,\"max_depth\":1,\"folded\":3}"
+        );
+    }
+}
@@ -1,17 +1,276 @@
-pub struct Tape(Vec<u8>);
+mod circular;
+mod fixed;
+mod growable;
+#[cfg(unix)]
+mod mmap;
+mod sparse;
 
-impl Default for Tape {
+pub use circular::CircularTape;
+pub use fixed::FixedTape;
+pub use growable::GrowableTape;
+#[cfg(unix)]
+pub use mmap::MmapTape;
+pub use sparse::SparseTape;
+
+use std::ops::Range;
+
+/// Cells on the tape unless configured otherwise, the classic 30000
+pub const DEFAULT_LEN: usize = 30 * 1000;
+/// Cells growable and sparse tapes may hold before running out of memory
+pub const DEFAULT_MEMORY_CAP: usize = 64 * 1024 * 1024;
+
+// Returned by tapes for cells that were never written to
+static ZERO: u8 = 0;
+
+/// Tape layout shared by the Machine and the generated code so every mode starts from the same state
+#[derive(Clone, Debug)]
+pub struct TapeConfig {
+    pub len: usize,
+    /// Cell the pointer starts at, counted from the leftmost cell
+    pub start: usize,
+    /// Copied onto the tape from the leftmost cell before the program runs
+    pub contents: Vec<u8>,
+}
+
+impl Default for TapeConfig {
     fn default() -> Self {
-        Self(vec![0u8; 30 * 1000])
+        Self {
+            len: DEFAULT_LEN,
+            start: 0,
+            contents: vec![],
+        }
+    }
+}
+
+impl TapeConfig {
+    /// Fails when the start cell or the contents do not fit on the tape
+    pub fn validate(&self) -> Result<(), String> {
+        if self.len == 0 {
+            return Err("tape length must be at least 1 cell".to_string());
+        }
+        if self.start >= self.len {
+            return Err(format!(
+                "start cell {} is outside a tape of {} cells",
+                self.start, self.len
+            ));
+        }
+        if self.contents.len() > self.len {
+            return Err(format!(
+                "{} bytes of initial contents do not fit a tape of {} cells",
+                self.contents.len(),
+                self.len
+            ));
+        }
+        Ok(())
+    }
+
+    /// Contents padded with zeroes to the full tape length
+    pub fn initial_cells(&self) -> Vec<u8> {
+        let mut cells = self.contents.clone();
+        cells.resize(self.len, 0);
+        cells
+    }
+}
+
+/// Cells are addressed from the leftmost cell of the initial tape, index 0, not from where the
+/// pointer starts. Growable and sparse tapes also have cells at negative indices left of it
+pub trait Tape {
+    fn get(&self, idx: isize) -> Option<&u8>;
+
+    /// May allocate storage for the cell, None when idx is out of the tape bounds
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8>;
+
+    /// Cells currently backed by storage, everything outside of it reads as zero
+    fn bounds(&self) -> Range<isize>;
+
+    /// The backed cells in ascending order when there are gaps between them
+    fn segments(&self) -> Vec<Range<isize>> {
+        vec![self.bounds()]
+    }
+
+    /// Maps the pointer after a move onto the cell it ends up at
+    fn wrap(&self, idx: isize) -> isize {
+        idx
     }
 }
 
-impl Tape {
-    pub fn get_mut(&mut self, idx: usize) -> Option<&mut u8> {
-        self.0.get_mut(idx)
+impl<T: Tape + ?Sized> Tape for Box<T> {
+    fn get(&self, idx: isize) -> Option<&u8> {
+        (**self).get(idx)
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        (**self).get_mut(idx)
     }
 
-    pub fn get(&mut self, idx: usize) -> Option<&u8> {
-        self.0.get(idx)
+    fn bounds(&self) -> Range<isize> {
+        (**self).bounds()
+    }
+
+    fn segments(&self) -> Vec<Range<isize>> {
+        (**self).segments()
+    }
+
+    fn wrap(&self, idx: isize) -> isize {
+        (**self).wrap(idx)
+    }
+}
+
+/// Accepts pairs of hex digits optionally prefixed with 0x and separated by whitespace
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.trim();
+    let digits = s
+        .strip_prefix("0x")
+        .unwrap_or(s)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex digit {}", c));
+    }
+    if digits.len() % 2 != 0 {
+        return Err("hex string has an odd number of digits".to_string());
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0].to_digit(16).unwrap() * 16 + pair[1].to_digit(16).unwrap()) as u8)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::TapeSnapshot;
+
+    #[test]
+    fn fixed_tape_rejects_cells_outside_of_it() {
+        let mut tape = FixedTape::new(3);
+        *tape.get_mut(2).unwrap() = 7;
+        assert_eq!(tape.get(2), Some(&7));
+        assert_eq!(tape.get(3), None);
+        assert!(tape.get_mut(-1).is_none());
+        assert_eq!(tape.bounds(), 0..3);
+    }
+
+    #[test]
+    fn growable_tape_grows_both_ways_up_to_its_cap() {
+        let mut tape = GrowableTape::new(2, 8);
+        *tape.get_mut(1).unwrap() = 1;
+        *tape.get_mut(-1).unwrap() = 2;
+        *tape.get_mut(3).unwrap() = 3;
+        assert_eq!(tape.get(-1), Some(&2));
+        assert_eq!(tape.get(1), Some(&1));
+        assert_eq!(tape.get(3), Some(&3));
+        assert!(tape.bounds().contains(&-1) && tape.bounds().contains(&3));
+        assert!(tape.bounds().len() <= 8);
+
+        // Reads past the allocated cells see zeroes until the cap is hit
+        assert_eq!(tape.get(5), Some(&0));
+        assert!(tape.get_mut(100).is_none());
+        assert_eq!(tape.get(100), None);
+    }
+
+    #[test]
+    fn sparse_tape_only_allocates_touched_pages() {
+        let mut tape = SparseTape::new(3 * 4096);
+        *tape.get_mut(-5).unwrap() = 1;
+        *tape.get_mut(1 << 40).unwrap() = 2;
+        assert_eq!(tape.get(-5), Some(&1));
+        assert_eq!(tape.get(1 << 40), Some(&2));
+        assert_eq!(tape.get(12345), Some(&0));
+
+        *tape.get_mut(4096 * 7).unwrap() = 3;
+        assert!(tape.get_mut(4096 * 9).is_none());
+        assert_eq!(tape.get(4096 * 9), Some(&0));
+    }
+
+    #[test]
+    fn sparse_tape_snapshot_skips_untouched_pages() {
+        let mut tape = SparseTape::default();
+        *tape.get_mut(-1).unwrap() = 1;
+        *tape.get_mut(0).unwrap() = 2;
+        *tape.get_mut(1 << 40).unwrap() = 3;
+        assert_eq!(
+            tape.segments(),
+            vec![-4096..4096, (1 << 40)..(1 << 40) + 4096]
+        );
+
+        let snapshot = TapeSnapshot::from_tape(&tape, 0);
+        assert_eq!(snapshot.segments.len(), 2);
+        assert_eq!(snapshot.get(-1), 1);
+        assert_eq!(snapshot.get(0), 2);
+        assert_eq!(snapshot.get(1 << 40), 3);
+        assert_eq!(snapshot.get(1 << 30), 0);
+    }
+
+    #[test]
+    fn sparse_tape_smaller_than_a_page() {
+        let mut tape = SparseTape::new(100);
+        assert!(tape.get_mut(0).is_some());
+        assert!(tape.get_mut(4096).is_none());
+    }
+
+    #[test]
+    fn contents_that_do_not_fit_are_an_error() {
+        use crate::machine::Machine;
+
+        let config = TapeConfig {
+            len: 9000,
+            start: 0,
+            contents: vec![1; 9000],
+        };
+        let error = Machine::new(SparseTape::new(100))
+            .with_config(&config)
+            .err();
+        assert_eq!(
+            error.map(|e| e.to_string()).as_deref(),
+            Some("initial contents do not fit the tape")
+        );
+        let machine = Machine::new(SparseTape::new(9000))
+            .with_config(&config)
+            .unwrap();
+        assert_eq!(machine.tape().get(8999), Some(&1));
+    }
+
+    #[test]
+    fn circular_tape_wraps_around() {
+        let mut tape = CircularTape::new(4);
+        assert_eq!(tape.wrap(-1), 3);
+        assert_eq!(tape.wrap(4), 0);
+        *tape.get_mut(-1).unwrap() = 9;
+        assert_eq!(tape.get(3), Some(&9));
+        assert_eq!(tape.get(7), Some(&9));
+    }
+
+    #[test]
+    fn parse_hex_accepts_one_prefix_and_whitespace() {
+        assert_eq!(parse_hex("0x01 ff\n2A"), Ok(vec![0x01, 0xff, 0x2a]));
+        assert_eq!(parse_hex(""), Ok(vec![]));
+        assert!(parse_hex("0x0x12").is_err());
+        assert!(parse_hex("+1").is_err());
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mmap_tapes() {
+        let mut tape = MmapTape::anonymous(1 << 20).unwrap();
+        *tape.get_mut((1 << 20) - 1).unwrap() = 5;
+        assert_eq!(tape.get((1 << 20) - 1), Some(&5));
+        assert_eq!(tape.get(1 << 20), None);
+        assert!(tape.get_mut(-1).is_none());
+
+        let path = std::env::temp_dir().join(format!("bf_tape_{}", std::process::id()));
+        {
+            let mut tape = MmapTape::file(&path, 16).unwrap();
+            *tape.get_mut(3).unwrap() = 42;
+        }
+        let bytes = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let bytes = bytes.unwrap();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(bytes[3], 42);
     }
 }
@@ -0,0 +1,36 @@
+use crate::tape::{Tape, DEFAULT_LEN};
+use std::ops::Range;
+
+/// Fixed number of cells where moving past either end wraps around to the other one
+pub struct CircularTape(Vec<u8>);
+
+impl Default for CircularTape {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEN)
+    }
+}
+
+impl CircularTape {
+    pub fn new(len: usize) -> Self {
+        Self(vec![0u8; len])
+    }
+}
+
+impl Tape for CircularTape {
+    fn get(&self, idx: isize) -> Option<&u8> {
+        self.0.get(self.wrap(idx) as usize)
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        let i = self.wrap(idx) as usize;
+        self.0.get_mut(i)
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        0..self.0.len() as isize
+    }
+
+    fn wrap(&self, idx: isize) -> isize {
+        idx.rem_euclid(self.0.len() as isize)
+    }
+}
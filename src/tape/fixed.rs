@@ -0,0 +1,31 @@
+use crate::tape::{Tape, DEFAULT_LEN};
+use std::ops::Range;
+
+/// Preallocated cells starting at the leftmost one
+pub struct FixedTape(Vec<u8>);
+
+impl Default for FixedTape {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEN)
+    }
+}
+
+impl FixedTape {
+    pub fn new(len: usize) -> Self {
+        Self(vec![0u8; len])
+    }
+}
+
+impl Tape for FixedTape {
+    fn get(&self, idx: isize) -> Option<&u8> {
+        self.0.get(usize::try_from(idx).ok()?)
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        self.0.get_mut(usize::try_from(idx).ok()?)
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        0..self.0.len() as isize
+    }
+}
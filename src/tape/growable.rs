@@ -0,0 +1,84 @@
+use crate::tape::{Tape, DEFAULT_LEN, DEFAULT_MEMORY_CAP, ZERO};
+use std::ops::Range;
+
+/// Contiguous cells that double on whichever side the pointer falls off of
+pub struct GrowableTape {
+    cells: Vec<u8>,
+    origin: usize,
+    cap: usize,
+}
+
+impl Default for GrowableTape {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEN, DEFAULT_MEMORY_CAP)
+    }
+}
+
+impl GrowableTape {
+    /// Starts out with len cells and grows in both directions up to cap cells
+    pub fn new(len: usize, cap: usize) -> Self {
+        Self {
+            cells: vec![0u8; len.min(cap)],
+            origin: 0,
+            cap,
+        }
+    }
+
+    fn index(&self, idx: isize) -> Option<usize> {
+        let i = self.origin.checked_add_signed(idx)?;
+        (i < self.cells.len()).then_some(i)
+    }
+
+    // Number of cells needed to cover both the current tape and idx
+    fn span_to(&self, idx: isize) -> usize {
+        let i = self.origin as isize + idx;
+        if i < 0 {
+            self.cells.len() + i.unsigned_abs()
+        } else {
+            self.cells.len().max(i as usize + 1)
+        }
+    }
+
+    fn grow(&mut self, idx: isize) {
+        let needed = self.span_to(idx);
+        if needed > self.cap {
+            return;
+        }
+        let len = self.cells.len();
+        let extra = (needed - len).max(len).min(self.cap - len);
+
+        if (self.origin as isize + idx) < 0 {
+            let mut cells = vec![0u8; extra];
+            cells.extend_from_slice(&self.cells);
+            self.cells = cells;
+            self.origin += extra;
+        } else {
+            self.cells.resize(len + extra, 0);
+        }
+    }
+}
+
+impl Tape for GrowableTape {
+    // Cells the tape hasn't reached yet read as zero without allocating them
+    fn get(&self, idx: isize) -> Option<&u8> {
+        match self.index(idx) {
+            Some(i) => self.cells.get(i),
+            None if self.span_to(idx) <= self.cap => Some(&ZERO),
+            None => None,
+        }
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        if self.index(idx).is_none() {
+            self.grow(idx);
+        }
+
+        let i = self.index(idx)?;
+        self.cells.get_mut(i)
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        let first = -(self.origin as isize);
+        first..first + self.cells.len() as isize
+    }
+}
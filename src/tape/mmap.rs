@@ -0,0 +1,92 @@
+use crate::tape::Tape;
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+/// Cells backed by an mmap region, anonymous ones are only paged in by the OS once touched
+pub struct MmapTape {
+    cells: *mut u8,
+    len: usize,
+}
+
+impl MmapTape {
+    /// Reserves len cells without committing memory up front
+    pub fn anonymous(len: usize) -> io::Result<Self> {
+        Self::map(
+            len,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+            -1,
+        )
+    }
+
+    /// Maps len cells of the file at path, creating or extending it as needed,
+    /// writes to the tape end up in the file
+    pub fn file(path: &Path, len: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < len as u64 {
+            file.set_len(len as u64)?;
+        }
+
+        // The mapping stays valid after the file is closed
+        Self::map(len, libc::MAP_SHARED, file.as_raw_fd())
+    }
+
+    fn map(len: usize, flags: libc::c_int, fd: libc::c_int) -> io::Result<Self> {
+        let cells = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                0,
+            )
+        };
+        if cells == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            cells: cells as *mut u8,
+            len,
+        })
+    }
+
+    fn cells(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.cells, self.len) }
+    }
+
+    fn cells_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.cells, self.len) }
+    }
+}
+
+impl Drop for MmapTape {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.cells as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+impl Tape for MmapTape {
+    fn get(&self, idx: isize) -> Option<&u8> {
+        self.cells().get(usize::try_from(idx).ok()?)
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        self.cells_mut().get_mut(usize::try_from(idx).ok()?)
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        0..self.len as isize
+    }
+}
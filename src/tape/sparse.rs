@@ -0,0 +1,80 @@
+use crate::tape::{Tape, DEFAULT_MEMORY_CAP, ZERO};
+use std::collections::HashMap;
+use std::ops::Range;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Only allocates the pages that were written to, for programs that jump far across the tape
+pub struct SparseTape {
+    pages: HashMap<isize, Box<[u8; PAGE_SIZE]>>,
+    max_pages: usize,
+}
+
+impl Default for SparseTape {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_CAP)
+    }
+}
+
+impl SparseTape {
+    /// Unbounded in both directions, allocates at most enough pages to hold cap cells
+    pub fn new(cap: usize) -> Self {
+        Self {
+            pages: HashMap::new(),
+            max_pages: cap.div_ceil(PAGE_SIZE),
+        }
+    }
+
+    fn split(idx: isize) -> (isize, usize) {
+        (
+            idx.div_euclid(PAGE_SIZE as isize),
+            idx.rem_euclid(PAGE_SIZE as isize) as usize,
+        )
+    }
+}
+
+impl Tape for SparseTape {
+    fn get(&self, idx: isize) -> Option<&u8> {
+        let (page, offset) = Self::split(idx);
+        match self.pages.get(&page) {
+            Some(cells) => Some(&cells[offset]),
+            None => Some(&ZERO),
+        }
+    }
+
+    fn get_mut(&mut self, idx: isize) -> Option<&mut u8> {
+        let (page, offset) = Self::split(idx);
+        if !self.pages.contains_key(&page) && self.pages.len() >= self.max_pages {
+            return None;
+        }
+
+        let cells = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| Box::new([0u8; PAGE_SIZE]));
+        Some(&mut cells[offset])
+    }
+
+    // Spans from the leftmost to the rightmost allocated page
+    fn bounds(&self) -> Range<isize> {
+        let first = self.pages.keys().min().copied().unwrap_or(0);
+        let last = self.pages.keys().max().copied().unwrap_or(-1);
+        first * PAGE_SIZE as isize..(last + 1) * PAGE_SIZE as isize
+    }
+
+    // Allocated pages with neighbouring ones merged
+    fn segments(&self) -> Vec<Range<isize>> {
+        let mut pages = self.pages.keys().copied().collect::<Vec<isize>>();
+        pages.sort_unstable();
+
+        let mut segments: Vec<Range<isize>> = vec![];
+        for page in pages {
+            let range = page * PAGE_SIZE as isize..(page + 1) * PAGE_SIZE as isize;
+            match segments.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => segments.push(range),
+            }
+        }
+        segments
+    }
+}
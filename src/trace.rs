@@ -0,0 +1,317 @@
+use crate::ast::Operator;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"BFTR";
+const VERSION: u8 = 1;
+const RECORD_LEN: usize = 26;
+
+/// File format a Tracer writes
+#[derive(Clone, Copy)]
+pub enum TraceFormat {
+    /// One line per step, easy to grep and diff
+    Text,
+    /// Fixed size records compressed with zstd for long runs
+    Binary,
+}
+
+enum Output {
+    Text(BufWriter<File>),
+    Binary(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+/// A single executed instruction, pointer and value are taken before it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub step: u64,
+    pub ptr: i64,
+    pub offset: u64,
+    /// 0 when the pointer is off the tape, text traces write - instead
+    pub value: u8,
+    pub op: u8,
+}
+
+impl TraceRecord {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..8].copy_from_slice(&self.step.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.ptr.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.offset.to_le_bytes());
+        buf[24] = self.value;
+        buf[25] = self.op;
+        buf
+    }
+
+    fn from_bytes(buf: &[u8; RECORD_LEN]) -> Self {
+        Self {
+            step: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            ptr: i64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            offset: u64::from_le_bytes(buf[16..24].try_into().unwrap()),
+            value: buf[24],
+            op: buf[25],
+        }
+    }
+}
+
+/// Writes a record of the pointer and cell for every step the Machine takes
+pub struct Tracer {
+    output: Output,
+    range: Option<Range<usize>>,
+    every: u64,
+    step: u64,
+}
+
+impl Tracer {
+    /// Truncates path and writes the header of format to it
+    pub fn create(path: &Path, format: TraceFormat) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let output = match format {
+            TraceFormat::Text => {
+                let mut file = file;
+                writeln!(file, "# step ptr value offset op")?;
+                Output::Text(file)
+            }
+            TraceFormat::Binary => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                encoder.write_all(MAGIC)?;
+                encoder.write_all(&[VERSION])?;
+                Output::Binary(encoder)
+            }
+        };
+
+        Ok(Self {
+            output,
+            range: None,
+            every: 1,
+            step: 0,
+        })
+    }
+
+    /// Only log instructions whose source offset falls inside the range
+    pub fn range(mut self, range: Range<usize>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Only log every nth step, steps are still counted while skipped
+    pub fn every(mut self, n: u64) -> Self {
+        self.every = n.max(1);
+        self
+    }
+
+    /// Logs op at source offset with the pointer and the cell under it, value is None while
+    /// the pointer is off the tape. Moves may take it there without the program failing
+    pub fn record(
+        &mut self,
+        offset: usize,
+        op: &Operator,
+        ptr: isize,
+        value: Option<u8>,
+    ) -> io::Result<()> {
+        let step = self.step;
+        self.step += 1;
+
+        if !step.is_multiple_of(self.every) {
+            return Ok(());
+        }
+        if let Some(range) = &self.range {
+            if !range.contains(&offset) {
+                return Ok(());
+            }
+        }
+
+        let record = TraceRecord {
+            step,
+            ptr: ptr as i64,
+            offset: offset as u64,
+            value: value.unwrap_or(0),
+            op: op.into(),
+        };
+
+        match &mut self.output {
+            Output::Text(w) => match value {
+                Some(value) => writeln!(
+                    w,
+                    "{} {} {} {} {}",
+                    record.step, record.ptr, value, record.offset, record.op as char
+                ),
+                None => writeln!(
+                    w,
+                    "{} {} - {} {}",
+                    record.step, record.ptr, record.offset, record.op as char
+                ),
+            },
+            Output::Binary(w) => w.write_all(&record.to_bytes()),
+        }
+    }
+
+    /// Flushes the file, binary traces are incomplete until this is called
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Text(mut w) => w.flush(),
+            Output::Binary(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+/// Iterates over the records of a binary trace file
+pub struct TraceReader(zstd::Decoder<'static, BufReader<File>>);
+
+impl TraceReader {
+    /// Fails unless path starts with the header of a binary trace of this version
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut decoder = zstd::Decoder::new(File::open(path)?)?;
+
+        let mut header = [0u8; 5];
+        decoder.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a brainfuck binary trace",
+            ));
+        }
+
+        Ok(Self(decoder))
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = io::Result<TraceRecord>;
+
+    // The end of the stream is only accepted between records, a trace cut off in the
+    // middle of one is reported as truncated
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0u8; RECORD_LEN];
+        let mut filled = 0;
+        while filled < RECORD_LEN {
+            match self.0.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "trace ends in the middle of a record",
+                    )))
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(TraceRecord::from_bytes(&buf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::machine::Machine;
+    use crate::tape::FixedTape;
+
+    fn trace(path: &Path, source: &[u8]) {
+        let ast = Ast::parse(source).unwrap();
+        let tracer = Tracer::create(path, TraceFormat::Binary).unwrap();
+        let mut machine = Machine::new(FixedTape::new(4)).with_tracer(tracer);
+        machine
+            .run_with_io(&ast, &mut io::empty(), &mut io::sink())
+            .unwrap();
+        machine.take_tracer().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn binary_trace_round_trips() {
+        let path = std::env::temp_dir().join(format!("bf_trace_{}.bin", std::process::id()));
+        trace(&path, b"+>+<");
+        let records = TraceReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>();
+        std::fs::remove_file(&path).unwrap();
+
+        let records = records.unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[2],
+            TraceRecord {
+                step: 2,
+                ptr: 1,
+                offset: 2,
+                value: 0,
+                op: b'+',
+            }
+        );
+    }
+
+    // Runs source on a 4 cell tape, with a text trace when path is given
+    fn run(source: &[u8], path: Option<&Path>) -> Result<isize, String> {
+        let ast = Ast::parse(source).unwrap();
+        let mut machine = Machine::new(FixedTape::new(4));
+        if let Some(path) = path {
+            machine = machine.with_tracer(Tracer::create(path, TraceFormat::Text).unwrap());
+        }
+        let result = machine.run_with_io(&ast, &mut io::empty(), &mut io::sink());
+        if let Some(tracer) = machine.take_tracer() {
+            tracer.finish().unwrap();
+        }
+        result
+            .map(|()| machine.pointer())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn tracing_does_not_change_the_program() {
+        let path = std::env::temp_dir().join(format!("bf_trace_{}.txt", std::process::id()));
+        for source in [&b"<>+"[..], b">>>>><<<<<<+"] {
+            assert_eq!(run(source, Some(&path)), run(source, None));
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The last trace is of >>>>><<<<<<+, which leaves the tape twice
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[4], "3 3 0 3 >");
+        assert_eq!(lines[5], "4 4 - 4 >");
+        assert_eq!(lines[12], "11 -1 - 11 +");
+        assert_eq!(
+            run(b">>>>><<<<<<+", None).unwrap_err(),
+            "pointer -1 is out of the tape bounds"
+        );
+    }
+
+    #[test]
+    fn cut_off_file_is_an_error() {
+        let path = std::env::temp_dir().join(format!("bf_trace_{}.zst", std::process::id()));
+        trace(&path, &b"+[>+<+]".repeat(200));
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let records = TraceReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(records.is_err());
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let path = std::env::temp_dir().join(format!("bf_trace_{}.cut", std::process::id()));
+        {
+            let mut encoder = zstd::Encoder::new(File::create(&path).unwrap(), 0).unwrap();
+            encoder.write_all(MAGIC).unwrap();
+            encoder.write_all(&[VERSION]).unwrap();
+            encoder.write_all(&[0; RECORD_LEN + 3]).unwrap();
+            encoder.finish().unwrap();
+        }
+        let mut reader = TraceReader::open(&path).unwrap();
+        let first = reader.next();
+        let second = reader.next();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(first, Some(Ok(_))));
+        assert_eq!(
+            second.unwrap().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
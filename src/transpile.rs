@@ -0,0 +1,312 @@
+mod c;
+mod rust;
+mod wasm;
+
+pub use c::to_c;
+pub use rust::to_rust;
+pub use wasm::{to_wasm, to_wat};
+
+use crate::ast::{Ast, Operator};
+use crate::tape::TapeConfig;
+
+/// Output of transpile, source code or a WebAssembly module
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Rust,
+    /// WebAssembly text format
+    Wat,
+    /// WebAssembly binary module
+    Wasm,
+}
+
+/// Width of a tape cell in the generated code, cells wrap around at its maximum
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum CellType {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellType {
+    pub fn bits(self) -> u32 {
+        match self {
+            CellType::U8 => 8,
+            CellType::U16 => 16,
+            CellType::U32 => 32,
+        }
+    }
+
+    // n reduced to the range of the cell, what adding n wraps around to
+    fn wrap(self, n: i64) -> u64 {
+        n.rem_euclid(1 << self.bits()) as u64
+    }
+}
+
+/// What , stores once input runs out, in translated programs and on every other backend
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Eof {
+    /// All bits set, what storing getchar's EOF does in compiled programs
+    #[default]
+    MinusOne,
+    Zero,
+    /// Leaves the cell as it was
+    Unchanged,
+}
+
+/// Shared by every source backend, cells wrap around at the width of cell
+#[derive(Clone, Default)]
+pub struct TranspileOptions {
+    pub tape: TapeConfig,
+    pub cell: CellType,
+    pub eof: Eof,
+    /// Moving past either end of the tape wraps around to the other one
+    pub circular: bool,
+}
+
+impl TranspileOptions {
+    // A move of n cells as the shortest move to the right that lands on the same cell of
+    // a circular tape
+    fn wrap_move(&self, n: i64) -> i64 {
+        n.rem_euclid(self.tape.len as i64)
+    }
+}
+
+/// Output of the backend for language, the text ones as UTF-8
+pub fn transpile(ast: &Ast, language: Language, options: &TranspileOptions) -> Vec<u8> {
+    match language {
+        Language::C => to_c(ast, options).into_bytes(),
+        Language::Rust => to_rust(ast, options).into_bytes(),
+        Language::Wat => to_wat(ast, options).into_bytes(),
+        Language::Wasm => to_wasm(ast, options),
+    }
+}
+
+// The Ast with runs of +- and <> folded together, which is what the backends emit code for
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Instr {
+    Add(i64),
+    Move(i64),
+    Out,
+    In,
+    Loop(Vec<Instr>),
+}
+
+pub(crate) fn lower(ast: &Ast) -> Vec<Instr> {
+    let mut instrs = vec![];
+    for op in ast.inner() {
+        match (op, instrs.last_mut()) {
+            (Operator::Inc, Some(Instr::Add(n))) => *n += 1,
+            (Operator::Dec, Some(Instr::Add(n))) => *n -= 1,
+            (Operator::IncPtr, Some(Instr::Move(n))) => *n += 1,
+            (Operator::DecPtr, Some(Instr::Move(n))) => *n -= 1,
+            (Operator::Inc, _) => instrs.push(Instr::Add(1)),
+            (Operator::Dec, _) => instrs.push(Instr::Add(-1)),
+            (Operator::IncPtr, _) => instrs.push(Instr::Move(1)),
+            (Operator::DecPtr, _) => instrs.push(Instr::Move(-1)),
+            (Operator::Out, _) => instrs.push(Instr::Out),
+            (Operator::In, _) => instrs.push(Instr::In),
+            (Operator::Loop(body), _) => instrs.push(Instr::Loop(lower(body))),
+        }
+    }
+
+    // Runs such as +- cancel out
+    instrs.retain(|instr| !matches!(instr, Instr::Add(0) | Instr::Move(0)));
+    instrs
+}
+
+// Whether f holds for any instruction, including the ones inside of loops
+fn contains(instrs: &[Instr], f: &impl Fn(&Instr) -> bool) -> bool {
+    instrs.iter().any(|instr| match instr {
+        Instr::Loop(body) => f(instr) || contains(body, f),
+        _ => f(instr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Write};
+    use std::path::Path;
+    use std::process::{Command, Output, Stdio};
+
+    // Builds what the backend for language generates from source and runs it on input.
+    // None when the tool it needs (cc, rustc or node) is not installed, which is
+    // reported, and fails the test instead when BF_REQUIRE_TOOLS is set
+    pub(super) fn run(
+        name: &str,
+        language: Language,
+        options: &TranspileOptions,
+        source: &[u8],
+        input: &[u8],
+    ) -> Option<Vec<u8>> {
+        let ast = Ast::parse(source).unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("bf_{:?}_{}_{}", language, name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("bf");
+
+        let output = match language {
+            Language::C => {
+                std::fs::write(dir.join("bf.c"), to_c(&ast, options)).unwrap();
+                let mut cc = Command::new("cc");
+                cc.arg("-o").arg(&exe).arg(dir.join("bf.c"));
+                build(cc, "cc").map(|()| execute(&exe, input))
+            }
+            Language::Rust => {
+                std::fs::write(dir.join("bf.rs"), to_rust(&ast, options)).unwrap();
+                std::fs::write(
+                    dir.join("main.rs"),
+                    "mod bf;\nfn main() {\n    bf::run(&mut std::io::stdin(), &mut std::io::stdout()).unwrap();\n}\n",
+                )
+                .unwrap();
+                let mut rustc = Command::new("rustc");
+                rustc
+                    .args(["--edition", "2021", "-D", "warnings", "-o"])
+                    .arg(&exe)
+                    .arg(dir.join("main.rs"));
+                build(rustc, "rustc").map(|()| execute(&exe, input))
+            }
+            Language::Wat | Language::Wasm => {
+                let module = dir.join("bf.wasm");
+                std::fs::write(&module, to_wasm(&ast, options)).unwrap();
+                found(node(&module, input), "node")
+            }
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let output = output?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(output.stdout)
+    }
+
+    fn build(mut command: Command, tool: &str) -> Option<()> {
+        let output = found(command.output(), tool)?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(())
+    }
+
+    fn found(output: io::Result<Output>, tool: &str) -> Option<Output> {
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                assert!(
+                    std::env::var_os("BF_REQUIRE_TOOLS").is_none(),
+                    "{} is not installed",
+                    tool
+                );
+                eprintln!("skipped, {} is not installed", tool);
+                None
+            }
+            output => Some(output.unwrap()),
+        }
+    }
+
+    fn execute(exe: &Path, input: &[u8]) -> Output {
+        let mut child = Command::new(exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    // Instantiates the module with node, env.read returns -1 once input runs out
+    fn node(module: &Path, input: &[u8]) -> io::Result<Output> {
+        let script = format!(
+            "const input = [{}]; const output = [];
+            const module = new WebAssembly.Module(require('fs').readFileSync(process.argv[1]));
+            const env = {{ read: () => input.length ? input.shift() : -1, write: c => output.push(c & 0xff) }};
+            new WebAssembly.Instance(module, {{ env }}).exports.run();
+            process.stdout.write(Buffer.from(output));",
+            input.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(",")
+        );
+        Command::new("node")
+            .arg("-e")
+            .arg(script)
+            .arg(module)
+            .output()
+    }
+
+    const BACKENDS: [Language; 3] = [Language::C, Language::Rust, Language::Wasm];
+
+    #[test]
+    fn hello_world_runs_on_every_backend() {
+        let source = include_bytes!("../exmaples/helloworld.bf");
+        for language in BACKENDS {
+            if let Some(output) = run("hello", language, &TranspileOptions::default(), source, b"")
+            {
+                assert_eq!(output, b"Hello World!\n");
+            }
+        }
+    }
+
+    #[test]
+    fn circular_tapes_wrap_both_ways() {
+        // Steps off the start to the last cell, then far enough right to come back to it
+        let options = TranspileOptions {
+            tape: TapeConfig {
+                len: 4,
+                start: 0,
+                contents: vec![1, 0, 0, 7],
+            },
+            circular: true,
+            ..Default::default()
+        };
+        for language in BACKENDS {
+            if let Some(output) = run("circular", language, &options, b"<.>>>>>+.<<<<<.", b"") {
+                assert_eq!(output, [7, 2, 7]);
+            }
+        }
+    }
+
+    #[test]
+    fn eof_behaviours() {
+        // Reads one byte past the end of input and prints what the cell holds
+        for language in BACKENDS {
+            for (eof, expected) in [(Eof::MinusOne, 255), (Eof::Zero, 0), (Eof::Unchanged, b'a')] {
+                let options = TranspileOptions {
+                    eof,
+                    ..Default::default()
+                };
+                if let Some(output) = run("eof", language, &options, b"+++,,.", b"a") {
+                    assert_eq!(output, [expected]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lower_folds_runs_and_drops_no_ops() {
+        let ast = Ast::parse(b"+++-->><<<[-+]..,").unwrap();
+        assert_eq!(
+            lower(&ast),
+            vec![
+                Instr::Add(1),
+                Instr::Move(-1),
+                Instr::Loop(vec![]),
+                Instr::Out,
+                Instr::Out,
+                Instr::In,
+            ]
+        );
+    }
+
+    #[test]
+    fn cell_wrap() {
+        assert_eq!(CellType::U8.wrap(-1), 255);
+        assert_eq!(CellType::U8.wrap(257), 1);
+        assert_eq!(CellType::U16.wrap(-1), 65535);
+        assert_eq!(CellType::U32.wrap(1 << 32), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{lower, CellType, Eof, Instr, TranspileOptions};
+use crate::ast::Ast;
+use std::fmt::Write;
+
+/// Translates the program to a standalone C file that builds with any C99 compiler
+pub fn to_c(ast: &Ast, options: &TranspileOptions) -> String {
+    let cell = match options.cell {
+        CellType::U8 => "uint8_t",
+        CellType::U16 => "uint16_t",
+        CellType::U32 => "uint32_t",
+    };
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by brainfuck-rs").unwrap();
+    writeln!(out, "#include <stdint.h>").unwrap();
+    writeln!(out, "#include <stdio.h>").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#define TAPE_LEN {}", options.tape.len).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "typedef {} cell;", cell).unwrap();
+    writeln!(out).unwrap();
+
+    if options.tape.contents.is_empty() {
+        writeln!(out, "static cell tape[TAPE_LEN];").unwrap();
+    } else {
+        writeln!(out, "static cell tape[TAPE_LEN] = {{").unwrap();
+        for row in options.tape.contents.chunks(16) {
+            let row = row.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            writeln!(out, "    {},", row.join(", ")).unwrap();
+        }
+        writeln!(out, "}};").unwrap();
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "int main(void) {{").unwrap();
+    match options.tape.start {
+        0 => writeln!(out, "    cell *p = tape;").unwrap(),
+        start => writeln!(out, "    cell *p = tape + {};", start).unwrap(),
+    }
+    write_block(&mut out, &lower(ast), options, 1);
+    writeln!(out, "    return 0;").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn write_block(out: &mut String, instrs: &[Instr], options: &TranspileOptions, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for instr in instrs {
+        match instr {
+            Instr::Add(n) if *n > 0 => writeln!(out, "{}*p += {};", indent, n),
+            Instr::Add(n) => writeln!(out, "{}*p -= {};", indent, -n),
+            Instr::Move(n) if options.circular => writeln!(
+                out,
+                "{}p = tape + (p - tape + {}) % TAPE_LEN;",
+                indent,
+                options.wrap_move(*n)
+            ),
+            Instr::Move(n) if *n > 0 => writeln!(out, "{}p += {};", indent, n),
+            Instr::Move(n) => writeln!(out, "{}p -= {};", indent, -n),
+            Instr::Out => writeln!(out, "{}putchar(*p);", indent),
+            Instr::In => match options.eof {
+                Eof::MinusOne => writeln!(out, "{}*p = (cell)getchar();", indent),
+                Eof::Zero => writeln!(
+                    out,
+                    "{}{{ int c = getchar(); *p = c == EOF ? 0 : (cell)c; }}",
+                    indent
+                ),
+                Eof::Unchanged => writeln!(
+                    out,
+                    "{}{{ int c = getchar(); if (c != EOF) *p = (cell)c; }}",
+                    indent
+                ),
+            },
+            Instr::Loop(body) => {
+                writeln!(out, "{}while (*p) {{", indent).unwrap();
+                write_block(out, body, options, depth + 1);
+                writeln!(out, "{}}}", indent)
+            }
+        }
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::TapeConfig;
+    use crate::transpile::{tests::run, Language};
+
+    #[test]
+    fn folds_runs_into_single_statements() {
+        let ast = Ast::parse(b"+++>>[-<]").unwrap();
+        let c = to_c(&ast, &TranspileOptions::default());
+        assert!(c.contains("#define TAPE_LEN 30000\n"));
+        assert!(c.contains("typedef uint8_t cell;\n"));
+        assert!(c.contains(
+            "    cell *p = tape;\n    *p += 3;\n    p += 2;\n    while (*p) {\n        *p -= 1;\n        p -= 1;\n    }\n"
+        ));
+    }
+
+    #[test]
+    fn initial_tape_and_start() {
+        let options = TranspileOptions {
+            tape: TapeConfig {
+                len: 4,
+                start: 2,
+                contents: vec![1, 2],
+            },
+            ..Default::default()
+        };
+        let c = to_c(&Ast::parse(b"").unwrap(), &options);
+        assert!(c.contains("static cell tape[TAPE_LEN] = {\n    1, 2,\n};\n"));
+        assert!(c.contains("    cell *p = tape + 2;\n"));
+    }
+
+    #[test]
+    fn wide_cells_wrap_at_their_width() {
+        // 256 only reads as zero when cells are bytes
+        let source = b"++++++++++++++++[>++++++++++++++++<-]>[>+<[-]]>+++++++++++++++++++++++++++++++++++++++++++++++++.";
+        for (cell, expected) in [(CellType::U8, b"1"), (CellType::U16, b"2")] {
+            let options = TranspileOptions {
+                cell,
+                ..Default::default()
+            };
+            if let Some(output) = run("wide", Language::C, &options, source, b"") {
+                assert_eq!(output, expected);
+            }
+        }
+    }
+}
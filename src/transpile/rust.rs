@@ -0,0 +1,195 @@
+use super::{contains, lower, CellType, Eof, Instr, TranspileOptions};
+use crate::ast::Ast;
+use std::fmt::Write;
+
+/// Translates the program to a standalone Rust module exposing
+/// pub fn run(input: &mut impl Read, output: &mut impl Write) -> io::Result<()>.
+/// Moving off of a tape that is not circular panics instead of touching other memory
+pub fn to_rust(ast: &Ast, options: &TranspileOptions) -> String {
+    let cell = match options.cell {
+        CellType::U8 => "u8",
+        CellType::U16 => "u16",
+        CellType::U32 => "u32",
+    };
+    let instrs = lower(ast);
+    let reads = contains(&instrs, &|instr| matches!(instr, Instr::In));
+    let mutates = contains(&instrs, &|instr| matches!(instr, Instr::In | Instr::Add(_)));
+    let moves = contains(&instrs, &|instr| matches!(instr, Instr::Move(_)));
+    // Keeps the generated code free of unused mut warnings
+    let mutable = |used: bool| if used { "mut " } else { "" };
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by brainfuck-rs").unwrap();
+    writeln!(out, "use std::io::{{self, Read, Write}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "const TAPE_LEN: usize = {};", options.tape.len).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "type Cell = {};", cell).unwrap();
+    writeln!(out).unwrap();
+
+    if reads {
+        writeln!(out, "// None once input runs out").unwrap();
+        writeln!(
+            out,
+            "fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {{"
+        )
+        .unwrap();
+        writeln!(out, "    let mut buf = [0u8; 1];").unwrap();
+        writeln!(out, "    loop {{").unwrap();
+        writeln!(out, "        match input.read(&mut buf) {{").unwrap();
+        writeln!(out, "            Ok(0) => return Ok(None),").unwrap();
+        writeln!(out, "            Ok(_) => return Ok(Some(buf[0])),").unwrap();
+        writeln!(
+            out,
+            "            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,"
+        )
+        .unwrap();
+        writeln!(out, "            Err(e) => return Err(e),").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+    }
+
+    writeln!(
+        out,
+        "pub fn run(input: &mut impl Read, output: &mut impl Write) -> io::Result<()> {{"
+    )
+    .unwrap();
+    if !reads {
+        writeln!(out, "    let _ = input;").unwrap();
+    }
+    if options.tape.contents.is_empty() {
+        writeln!(
+            out,
+            "    let {}tape: Vec<Cell> = vec![0; TAPE_LEN];",
+            mutable(mutates)
+        )
+        .unwrap();
+    } else {
+        writeln!(out, "    let mut tape: Vec<Cell> = vec![0; TAPE_LEN];").unwrap();
+        let contents = options
+            .tape
+            .contents
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "    tape[..{}].copy_from_slice(&[{}]);",
+            contents.len(),
+            contents.join(", ")
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "    let {}p: usize = {};",
+        mutable(moves),
+        options.tape.start
+    )
+    .unwrap();
+    write_block(&mut out, &instrs, options, 1);
+    writeln!(out, "    output.flush()").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn write_block(out: &mut String, instrs: &[Instr], options: &TranspileOptions, depth: usize) {
+    let indent = "    ".repeat(depth);
+    // Conversions between bytes and cells, which are no-ops for u8 cells
+    let (to_byte, from_byte) = match options.cell {
+        CellType::U8 => ("tape[p]", "byte"),
+        _ => ("tape[p] as u8", "Cell::from(byte)"),
+    };
+    for instr in instrs {
+        match instr {
+            Instr::Add(n) => {
+                let up = options.cell.wrap(*n);
+                let down = options.cell.wrap(-n);
+                match up <= down {
+                    true => writeln!(out, "{}tape[p] = tape[p].wrapping_add({});", indent, up),
+                    false => writeln!(out, "{}tape[p] = tape[p].wrapping_sub({});", indent, down),
+                }
+            }
+            Instr::Move(n) if options.circular => writeln!(
+                out,
+                "{}p = (p + {}) % TAPE_LEN;",
+                indent,
+                options.wrap_move(*n)
+            ),
+            Instr::Move(n) if *n > 0 => writeln!(out, "{}p += {};", indent, n),
+            Instr::Move(n) => writeln!(out, "{}p -= {};", indent, -n),
+            // Only the low byte is written like putchar does
+            Instr::Out => writeln!(out, "{}output.write_all(&[{}])?;", indent, to_byte),
+            Instr::In => {
+                // Prompts have to be visible before blocking on input
+                writeln!(out, "{}output.flush()?;", indent).unwrap();
+                match options.eof {
+                    Eof::MinusOne => writeln!(
+                        out,
+                        "{}tape[p] = read_byte(input)?.map_or(Cell::MAX, |byte| {});",
+                        indent, from_byte
+                    ),
+                    Eof::Zero => writeln!(
+                        out,
+                        "{}tape[p] = read_byte(input)?.map_or(0, |byte| {});",
+                        indent, from_byte
+                    ),
+                    Eof::Unchanged => writeln!(
+                        out,
+                        "{}if let Some(byte) = read_byte(input)? {{ tape[p] = {}; }}",
+                        indent, from_byte
+                    ),
+                }
+            }
+            Instr::Loop(body) => {
+                writeln!(out, "{}while tape[p] != 0 {{", indent).unwrap();
+                write_block(out, body, options, depth + 1);
+                writeln!(out, "{}}}", indent)
+            }
+        }
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transpile::{tests::run, Language};
+
+    #[test]
+    fn adds_take_the_shorter_way_around() {
+        let minus = to_rust(&Ast::parse(b"-").unwrap(), &TranspileOptions::default());
+        assert!(minus.contains("    tape[p] = tape[p].wrapping_sub(1);\n"));
+
+        let ast = Ast::parse("+".repeat(255).as_bytes()).unwrap();
+        let u8_cells = to_rust(&ast, &TranspileOptions::default());
+        assert!(u8_cells.contains("    tape[p] = tape[p].wrapping_sub(1);\n"));
+        let options = TranspileOptions {
+            cell: CellType::U16,
+            ..Default::default()
+        };
+        assert!(to_rust(&ast, &options).contains("    tape[p] = tape[p].wrapping_add(255);\n"));
+    }
+
+    #[test]
+    fn only_reads_when_the_program_does() {
+        let module = to_rust(&Ast::parse(b".").unwrap(), &TranspileOptions::default());
+        assert!(!module.contains("fn read_byte"));
+        assert!(module.contains("    let _ = input;\n"));
+        assert!(module.contains("    let tape: Vec<Cell>"));
+        assert!(module.contains("    let p: usize = 0;\n"));
+    }
+
+    #[test]
+    fn wide_cells_write_their_low_byte() {
+        let options = TranspileOptions {
+            cell: CellType::U32,
+            ..Default::default()
+        };
+        if let Some(output) = run("wide", Language::Rust, &options, b",+++.", b"\xff") {
+            assert_eq!(output, [2]);
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use super::{lower, CellType, Eof, Instr, TranspileOptions};
+use crate::ast::Ast;
+use std::fmt::Write;
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+// Indices of the imported functions, run comes right after them
+const READ_FN: u32 = 0;
+const WRITE_FN: u32 = 1;
+const RUN_FN: u32 = 2;
+
+#[derive(Clone, Copy)]
+enum Local {
+    P, // Byte address of the current cell
+    C, // Last value returned by read
+}
+
+// The subset of WebAssembly instructions the backend needs, rendered as text or bytes
+enum Op {
+    LocalGet(Local),
+    LocalSet(Local),
+    I32Const(i32),
+    I32Add,
+    I32RemU,
+    I32Eqz,
+    I32GeS,
+    Load,
+    Store,
+    Call(u32),
+    Select,
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+}
+
+/// Translates the program to the WebAssembly text format. The module imports
+/// env.read: () -> i32, which returns a byte or a negative value at the end of input,
+/// and env.write: (i32) -> () receiving cell values, and exports its memory and run
+pub fn to_wat(ast: &Ast, options: &TranspileOptions) -> String {
+    let mut out = String::new();
+    writeln!(out, ";; Generated by brainfuck-rs").unwrap();
+    writeln!(out, "(module").unwrap();
+    writeln!(out, "  (import \"env\" \"read\" (func $read (result i32)))").unwrap();
+    writeln!(
+        out,
+        "  (import \"env\" \"write\" (func $write (param i32)))"
+    )
+    .unwrap();
+    writeln!(out, "  (memory (export \"memory\") {})", pages(options)).unwrap();
+
+    let data = initial_data(options);
+    if !data.is_empty() {
+        let escaped = data
+            .iter()
+            .map(|b| format!("\\{:02x}", b))
+            .collect::<String>();
+        writeln!(out, "  (data (i32.const 0) \"{}\")", escaped).unwrap();
+    }
+
+    writeln!(out, "  (func (export \"run\")").unwrap();
+    writeln!(out, "    (local $p i32) (local $c i32)").unwrap();
+    let mut depth = 2;
+    for op in body(ast, options) {
+        if matches!(op, Op::End) {
+            depth -= 1;
+        }
+        let text = match op {
+            Op::LocalGet(local) => format!("local.get {}", local_name(local)),
+            Op::LocalSet(local) => format!("local.set {}", local_name(local)),
+            Op::I32Const(n) => format!("i32.const {}", n),
+            Op::I32Add => "i32.add".to_string(),
+            Op::I32RemU => "i32.rem_u".to_string(),
+            Op::I32Eqz => "i32.eqz".to_string(),
+            Op::I32GeS => "i32.ge_s".to_string(),
+            Op::Load => match options.cell {
+                CellType::U8 => "i32.load8_u".to_string(),
+                CellType::U16 => "i32.load16_u".to_string(),
+                CellType::U32 => "i32.load".to_string(),
+            },
+            Op::Store => match options.cell {
+                CellType::U8 => "i32.store8".to_string(),
+                CellType::U16 => "i32.store16".to_string(),
+                CellType::U32 => "i32.store".to_string(),
+            },
+            Op::Call(READ_FN) => "call $read".to_string(),
+            Op::Call(WRITE_FN) => "call $write".to_string(),
+            Op::Call(f) => format!("call {}", f),
+            Op::Select => "select".to_string(),
+            Op::Block => "block".to_string(),
+            Op::Loop => "loop".to_string(),
+            Op::If => "if".to_string(),
+            Op::End => "end".to_string(),
+            Op::Br(label) => format!("br {}", label),
+            Op::BrIf(label) => format!("br_if {}", label),
+        };
+        writeln!(out, "{}{}", "  ".repeat(depth), text).unwrap();
+        if matches!(op, Op::Block | Op::Loop | Op::If) {
+            depth += 1;
+        }
+    }
+    writeln!(out, "  )").unwrap();
+    writeln!(out, ")").unwrap();
+    out
+}
+
+/// Same module as to_wat in the binary format
+pub fn to_wasm(ast: &Ast, options: &TranspileOptions) -> Vec<u8> {
+    let mut out = b"\0asm".to_vec();
+    out.extend_from_slice(&1u32.to_le_bytes());
+
+    // read: () -> i32, write: (i32) -> (), run: () -> ()
+    let types = [
+        vec![0x60, 0x00, 0x01, 0x7f],
+        vec![0x60, 0x01, 0x7f, 0x00],
+        vec![0x60, 0x00, 0x00],
+    ];
+    section(&mut out, 1, &vector(&types));
+
+    let imports = [
+        [name("env"), name("read"), vec![0x00, READ_FN as u8]].concat(),
+        [name("env"), name("write"), vec![0x00, WRITE_FN as u8]].concat(),
+    ];
+    section(&mut out, 2, &vector(&imports));
+
+    // run has the third type
+    section(&mut out, 3, &vector(&[vec![0x02]]));
+
+    let mut limits = vec![0x00];
+    unsigned(&mut limits, pages(options) as u64);
+    section(&mut out, 5, &vector(&[limits]));
+
+    let mut run_export = [name("run"), vec![0x00]].concat();
+    unsigned(&mut run_export, RUN_FN as u64);
+    let memory_export = [name("memory"), vec![0x02, 0x00]].concat();
+    section(&mut out, 7, &vector(&[run_export, memory_export]));
+
+    // Two i32 locals, p and c
+    let mut code = vec![0x01, 0x02, 0x7f];
+    for op in body(ast, options) {
+        encode(&mut code, op, options.cell);
+    }
+    code.push(0x0b);
+    let mut function = vec![];
+    unsigned(&mut function, code.len() as u64);
+    function.extend(code);
+    section(&mut out, 10, &vector(&[function]));
+
+    let data = initial_data(options);
+    if !data.is_empty() {
+        // Active segment for memory 0 at address 0
+        let mut segment = vec![0x00, 0x41, 0x00, 0x0b];
+        unsigned(&mut segment, data.len() as u64);
+        segment.extend(data);
+        section(&mut out, 11, &vector(&[segment]));
+    }
+
+    out
+}
+
+fn local_name(local: Local) -> &'static str {
+    match local {
+        Local::P => "$p",
+        Local::C => "$c",
+    }
+}
+
+fn cell_size(cell: CellType) -> usize {
+    cell.bits() as usize / 8
+}
+
+fn pages(options: &TranspileOptions) -> usize {
+    (options.tape.len * cell_size(options.cell)).div_ceil(PAGE_SIZE)
+}
+
+// Initial contents laid out as little endian cells
+fn initial_data(options: &TranspileOptions) -> Vec<u8> {
+    let size = cell_size(options.cell);
+    options
+        .tape
+        .contents
+        .iter()
+        .flat_map(|c| (*c as u32).to_le_bytes().into_iter().take(size))
+        .collect()
+}
+
+fn body(ast: &Ast, options: &TranspileOptions) -> Vec<Op> {
+    let mut ops = vec![
+        Op::I32Const((options.tape.start * cell_size(options.cell)) as i32),
+        Op::LocalSet(Local::P),
+    ];
+    lower_block(&mut ops, &lower(ast), options);
+    ops
+}
+
+fn lower_block(ops: &mut Vec<Op>, instrs: &[Instr], options: &TranspileOptions) {
+    let size = cell_size(options.cell) as i64;
+    for instr in instrs {
+        match instr {
+            Instr::Add(n) => ops.extend([
+                Op::LocalGet(Local::P),
+                Op::LocalGet(Local::P),
+                Op::Load,
+                Op::I32Const(options.cell.wrap(*n) as u32 as i32),
+                Op::I32Add,
+                Op::Store,
+            ]),
+            // p stays below the tape so the addition can not overflow
+            Instr::Move(n) if options.circular => ops.extend([
+                Op::LocalGet(Local::P),
+                Op::I32Const((options.wrap_move(*n) * size) as i32),
+                Op::I32Add,
+                Op::I32Const((options.tape.len as i64 * size) as i32),
+                Op::I32RemU,
+                Op::LocalSet(Local::P),
+            ]),
+            Instr::Move(n) => ops.extend([
+                Op::LocalGet(Local::P),
+                Op::I32Const((n * size) as i32),
+                Op::I32Add,
+                Op::LocalSet(Local::P),
+            ]),
+            Instr::Out => ops.extend([Op::LocalGet(Local::P), Op::Load, Op::Call(WRITE_FN)]),
+            Instr::In => match options.eof {
+                // Storing -1 sets every bit of the cell
+                Eof::MinusOne => ops.extend([Op::LocalGet(Local::P), Op::Call(READ_FN), Op::Store]),
+                Eof::Zero => ops.extend([
+                    Op::Call(READ_FN),
+                    Op::LocalSet(Local::C),
+                    Op::LocalGet(Local::P),
+                    Op::LocalGet(Local::C),
+                    Op::I32Const(0),
+                    Op::LocalGet(Local::C),
+                    Op::I32Const(0),
+                    Op::I32GeS,
+                    Op::Select,
+                    Op::Store,
+                ]),
+                Eof::Unchanged => ops.extend([
+                    Op::Call(READ_FN),
+                    Op::LocalSet(Local::C),
+                    Op::LocalGet(Local::C),
+                    Op::I32Const(0),
+                    Op::I32GeS,
+                    Op::If,
+                    Op::LocalGet(Local::P),
+                    Op::LocalGet(Local::C),
+                    Op::Store,
+                    Op::End,
+                ]),
+            },
+            Instr::Loop(body) => {
+                ops.extend([
+                    Op::Block,
+                    Op::Loop,
+                    Op::LocalGet(Local::P),
+                    Op::Load,
+                    Op::I32Eqz,
+                    Op::BrIf(1),
+                ]);
+                lower_block(ops, body, options);
+                ops.extend([Op::Br(0), Op::End, Op::End]);
+            }
+        }
+    }
+}
+
+fn encode(code: &mut Vec<u8>, op: Op, cell: CellType) {
+    // Alignment hint as a power of two followed by offset 0
+    let memarg = [cell_size(cell).trailing_zeros() as u8, 0x00];
+    match op {
+        Op::LocalGet(local) => code.extend([0x20, local as u8]),
+        Op::LocalSet(local) => code.extend([0x21, local as u8]),
+        Op::I32Const(n) => {
+            code.push(0x41);
+            signed(code, n as i64);
+        }
+        Op::I32Add => code.push(0x6a),
+        Op::I32RemU => code.push(0x70),
+        Op::I32Eqz => code.push(0x45),
+        Op::I32GeS => code.push(0x4e),
+        Op::Load => {
+            code.push(match cell {
+                CellType::U8 => 0x2d,
+                CellType::U16 => 0x2f,
+                CellType::U32 => 0x28,
+            });
+            code.extend(memarg);
+        }
+        Op::Store => {
+            code.push(match cell {
+                CellType::U8 => 0x3a,
+                CellType::U16 => 0x3b,
+                CellType::U32 => 0x36,
+            });
+            code.extend(memarg);
+        }
+        Op::Call(f) => {
+            code.push(0x10);
+            unsigned(code, f as u64);
+        }
+        Op::Select => code.push(0x1b),
+        Op::Block => code.extend([0x02, 0x40]),
+        Op::Loop => code.extend([0x03, 0x40]),
+        Op::If => code.extend([0x04, 0x40]),
+        Op::End => code.push(0x0b),
+        Op::Br(label) => {
+            code.push(0x0c);
+            unsigned(code, label as u64);
+        }
+        Op::BrIf(label) => {
+            code.push(0x0d);
+            unsigned(code, label as u64);
+        }
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    unsigned(out, contents.len() as u64);
+    out.extend_from_slice(contents);
+}
+
+fn vector(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![];
+    unsigned(&mut out, items.len() as u64);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+fn name(s: &str) -> Vec<u8> {
+    let mut out = vec![];
+    unsigned(&mut out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+// LEB128
+fn unsigned(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        let done = (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::TapeConfig;
+    use crate::transpile::{tests::run, Language};
+
+    fn leb(f: fn(&mut Vec<u8>, i64), n: i64) -> Vec<u8> {
+        let mut out = vec![];
+        f(&mut out, n);
+        out
+    }
+
+    #[test]
+    fn unsigned_leb128() {
+        let unsigned = |out: &mut Vec<u8>, n: i64| unsigned(out, n as u64);
+        assert_eq!(leb(unsigned, 0), [0x00]);
+        assert_eq!(leb(unsigned, 127), [0x7f]);
+        assert_eq!(leb(unsigned, 128), [0x80, 0x01]);
+        assert_eq!(leb(unsigned, 624485), [0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn signed_leb128() {
+        assert_eq!(leb(signed, 0), [0x00]);
+        assert_eq!(leb(signed, 63), [0x3f]);
+        assert_eq!(leb(signed, 64), [0xc0, 0x00]);
+        assert_eq!(leb(signed, -1), [0x7f]);
+        assert_eq!(leb(signed, -64), [0x40]);
+        assert_eq!(leb(signed, -65), [0xbf, 0x7f]);
+        assert_eq!(leb(signed, -123456), [0xc0, 0xbb, 0x78]);
+    }
+
+    #[test]
+    fn module_layout() {
+        let options = TranspileOptions {
+            tape: TapeConfig {
+                len: PAGE_SIZE + 1,
+                start: 0,
+                contents: vec![1, 2],
+            },
+            ..Default::default()
+        };
+        let wasm = to_wasm(&Ast::parse(b"").unwrap(), &options);
+        assert_eq!(wasm[..8], *b"\0asm\x01\0\0\0");
+        // Memory section with a single two page memory
+        assert!(wasm.windows(5).any(|w| w == [0x05, 0x03, 0x01, 0x00, 0x02]));
+
+        let wat = to_wat(&Ast::parse(b"").unwrap(), &options);
+        assert!(wat.contains("  (memory (export \"memory\") 2)\n"));
+        assert!(wat.contains("  (data (i32.const 0) \"\\01\\02\")\n"));
+    }
+
+    #[test]
+    fn wat_nests_loops() {
+        let wat = to_wat(&Ast::parse(b"[-]").unwrap(), &TranspileOptions::default());
+        assert!(wat.contains("    block\n      loop\n        local.get $p\n"));
+        assert!(wat.contains("        br 0\n      end\n    end\n"));
+    }
+
+    #[test]
+    fn wide_cells_and_start() {
+        // Moves left of the start cell and wraps a 16 bit cell below zero
+        let options = TranspileOptions {
+            tape: TapeConfig {
+                len: 4,
+                start: 1,
+                contents: vec![0, 7],
+            },
+            cell: CellType::U16,
+            ..Default::default()
+        };
+        if let Some(output) = run("wide", Language::Wasm, &options, b"<-.>+.", b"") {
+            assert_eq!(output, [0xff, 8]);
+        }
+    }
+}
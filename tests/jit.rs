@@ -0,0 +1,137 @@
+#![cfg(feature = "llvm")]
+
+use brainfuck_rs::ast::Ast;
+use brainfuck_rs::codegen::IRCodegen;
+use brainfuck_rs::dump::TapeSnapshot;
+use brainfuck_rs::program::Program;
+use brainfuck_rs::tape::TapeConfig;
+use brainfuck_rs::transpile::Eof;
+use inkwell::OptimizationLevel;
+use std::path::Path;
+
+// Programs that read their input, some of them past its end
+const IO_PROGRAMS: &[(&str, &[u8])] = &[
+    (",.", b"a"),
+    (",+.,+.,+.", b"abc"),
+    (">,>,>,[.<]", b"xyz"),
+    (",>,[-<+>]<.", b"\x10\x20"),
+    (",[->+>+<<]>.>.", b"\x05"),
+    ("+++,,.", b"a"),
+    (",.,.,.,.", b"ab"),
+];
+
+// The interpreter is what every other backend is checked against
+fn run_machine(
+    source: &[u8],
+    config: &TapeConfig,
+    eof: Eof,
+    input: &[u8],
+) -> (Vec<u8>, TapeSnapshot) {
+    let program = Program::parse(source)
+        .unwrap()
+        .tape(config.clone())
+        .eof(eof);
+    let mut output = vec![];
+    let snapshot = program.run(&mut &input[..], &mut output).unwrap();
+    (output, snapshot)
+}
+
+fn run_jit(
+    ast: &Ast,
+    config: &TapeConfig,
+    eof: Eof,
+    level: OptimizationLevel,
+    input: &[u8],
+) -> (Vec<u8>, TapeSnapshot) {
+    let mut output = vec![];
+    let snapshot = IRCodegen::from(ast)
+        .tape_config(config.clone())
+        .eof(eof)
+        .optimize(level)
+        .jit_opt_level(level)
+        .dump_tape()
+        .jit_with_io(&mut &input[..], &mut output)
+        .unwrap()
+        .expect("dump_tape hands back the tape");
+    (output, snapshot)
+}
+
+fn assert_same(source: &[u8], config: &TapeConfig, input: &[u8]) {
+    let ast = Ast::parse(source).unwrap();
+    for eof in [Eof::MinusOne, Eof::Zero, Eof::Unchanged] {
+        let expected = run_machine(source, config, eof, input);
+        for level in [OptimizationLevel::None, OptimizationLevel::Aggressive] {
+            let actual = run_jit(&ast, config, eof, level, input);
+            assert_eq!(
+                actual,
+                expected,
+                "{} at {:?} with {:?}",
+                String::from_utf8_lossy(source),
+                level,
+                eof
+            );
+        }
+    }
+}
+
+#[test]
+fn examples_match_the_machine() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("exmaples");
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "bf") {
+            assert_same(&std::fs::read(&path).unwrap(), &TapeConfig::default(), b"");
+            count += 1;
+        }
+    }
+    assert!(count > 0, "no examples found");
+}
+
+#[test]
+fn io_programs_match_the_machine() {
+    for (source, input) in IO_PROGRAMS {
+        assert_same(source.as_bytes(), &TapeConfig::default(), input);
+    }
+}
+
+#[test]
+fn configured_tape_matches_the_machine() {
+    let config = TapeConfig {
+        len: 8,
+        start: 3,
+        contents: vec![1, 2, 3, 4],
+    };
+    assert_same(b"[-<+>]<<[->>+<<]>+.", &config, b"");
+    assert_same(b",<,>>,.<.<.", &config, b"abc");
+}
+
+#[test]
+fn out_of_bounds_is_an_error() {
+    let ast = Ast::parse(b"<+").unwrap();
+    let result = IRCodegen::from(&ast)
+        .checked()
+        .jit_with_io(&mut &b""[..], &mut vec![]);
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("out of bounds"), "{}", error);
+}
+
+#[test]
+fn run_on_a_tape_of_the_wrong_length_is_an_error() {
+    let ast = Ast::parse(b"+").unwrap();
+    let codegen = IRCodegen::from(&ast).tape_config(TapeConfig {
+        len: 8,
+        ..TapeConfig::default()
+    });
+    let mut program = codegen.jit_program().unwrap();
+    let result = program.run_on(&mut [0; 4], &mut &b""[..], &mut vec![]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "tape has 4 cells but the program was compiled for 8"
+    );
+    let mut tape = [0; 8];
+    program
+        .run_on(&mut tape, &mut &b""[..], &mut vec![])
+        .unwrap();
+    assert_eq!(tape[0], 1);
+}